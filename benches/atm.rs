@@ -0,0 +1,166 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use toy_atm::accounting::{
+    atm::Atm,
+    common::{Amount, ClientID, TransactionID},
+    pipeline::{PipelineConfig, TransactionPipeline},
+    transaction::{self, Transaction, TransactionReader, TransactionType},
+};
+
+/// Number of transactions of every workload.
+const TRANSACTIONS: u32 = 10_000;
+
+/// Generates a workload where `dispute_rate` of the rows dispute, resolve or
+/// charge back an earlier transaction of the same client.
+fn workload(clients: u16, dispute_rate: f64) -> Vec<(ClientID, TransactionID, TransactionType)> {
+    use TransactionType::*;
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut transactions: Vec<(ClientID, TransactionID, TransactionType)> = Vec::new();
+    for transaction_id in 1..=TRANSACTIONS {
+        let referenced = (!transactions.is_empty() && rng.gen_bool(dispute_rate))
+            .then(|| transactions[rng.gen_range(0..transactions.len())]);
+        let transaction = match referenced {
+            Some((client_id, transaction_id, _)) => {
+                let transaction_type = match rng.gen_range(0..10) {
+                    0..=5 => Dispute,
+                    6..=8 => Resolve,
+                    _ => Chargeback,
+                };
+                (client_id, transaction_id, transaction_type)
+            }
+            None => {
+                let client_id = ClientID(rng.gen_range(1..=clients));
+                let amount = Amount::new(rng.gen_range(0.0001..1000.0));
+                let transaction_type = if rng.gen_bool(0.2) {
+                    Withdrawal(amount)
+                } else {
+                    Deposit(amount)
+                };
+                (client_id, TransactionID(transaction_id), transaction_type)
+            }
+        };
+        transactions.push(transaction);
+    }
+    transactions
+}
+
+fn handle_all(transactions: &[(ClientID, TransactionID, TransactionType)]) -> Atm {
+    let mut atm = Atm::default();
+    for &(client_id, transaction_id, transaction_type) in transactions {
+        _ = atm.handle_transaction(Transaction {
+            client_id,
+            transaction_id,
+            transaction_type,
+            currency: None,
+        });
+    }
+    atm
+}
+
+fn to_csv(transactions: &[(ClientID, TransactionID, TransactionType)]) -> String {
+    use TransactionType::*;
+    let mut csv = String::from("type, client, tx, amount\n");
+    for (client_id, transaction_id, transaction_type) in transactions {
+        let (record_type, amount) = match transaction_type {
+            Deposit(amount) => ("deposit", Some(amount)),
+            Withdrawal(amount) => ("withdrawal", Some(amount)),
+            Dispute => ("dispute", None),
+            Resolve => ("resolve", None),
+            _ => ("chargeback", None),
+        };
+        let amount = amount.map(|amount| f64::from(*amount).to_string());
+        csv.push_str(&format!(
+            "{record_type}, {}, {}, {}\n",
+            client_id.0,
+            transaction_id.0,
+            amount.unwrap_or_default()
+        ));
+    }
+    csv
+}
+
+fn bench_handle_transaction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handle_transaction");
+    group.throughput(Throughput::Elements(u64::from(TRANSACTIONS)));
+    for (name, clients, dispute_rate) in [
+        ("deposit_heavy", 100, 0.0),
+        ("dispute_heavy", 100, 0.4),
+        ("many_clients", u16::MAX, 0.05),
+    ] {
+        let transactions = workload(clients, dispute_rate);
+        group.bench_function(name, |b| b.iter(|| handle_all(&transactions)));
+    }
+    group.finish();
+}
+
+/// Compares the two ways of reading transactions, see the Ingestion section
+/// of the README for end-to-end numbers.
+fn bench_deserialize(c: &mut Criterion) {
+    let csv = to_csv(&workload(100, 0.05));
+    let mut group = c.benchmark_group("deserialize");
+    group.throughput(Throughput::Bytes(csv.len() as u64));
+    group.bench_function("serde", |b| {
+        b.iter(|| {
+            transaction::csv_reader(csv.as_bytes())
+                .into_deserialize::<Transaction>()
+                .flatten()
+                .count()
+        })
+    });
+    group.bench_function("byte_record", |b| {
+        b.iter(|| TransactionReader::new(csv.as_bytes()).flatten().count())
+    });
+    group.finish();
+}
+
+fn bench_read_and_handle(c: &mut Criterion) {
+    let csv = to_csv(&workload(100, 0.05));
+    let mut group = c.benchmark_group("read_and_handle");
+    group.throughput(Throughput::Bytes(csv.len() as u64));
+    let handle = |transactions: &mut dyn Iterator<Item = Transaction>| {
+        let mut atm = Atm::default();
+        for tx in transactions {
+            _ = atm.handle_transaction(tx);
+        }
+        atm
+    };
+    group.bench_function("reader", |b| {
+        b.iter(|| handle(&mut TransactionReader::new(csv.as_bytes()).flatten()))
+    });
+    group.bench_function("pipeline", |b| {
+        b.iter(|| {
+            let input = std::io::Cursor::new(csv.clone());
+            handle(&mut TransactionPipeline::new(input, PipelineConfig::default()).flatten())
+        })
+    });
+    group.finish();
+}
+
+fn bench_serialize(c: &mut Criterion) {
+    let atm = handle_all(&workload(u16::MAX, 0.05));
+    let mut group = c.benchmark_group("serialize");
+    group.throughput(Throughput::Elements(atm.accounts().count() as u64));
+    group.bench_function("accounts", |b| {
+        b.iter_batched(
+            || Vec::with_capacity(1 << 20),
+            |output| {
+                let mut csv_writer = csv::Writer::from_writer(output);
+                for snapshot in atm.accounts() {
+                    csv_writer.serialize(snapshot).unwrap();
+                }
+                csv_writer.into_inner().unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_handle_transaction,
+    bench_deserialize,
+    bench_read_and_handle,
+    bench_serialize
+);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use toy_atm::accounting::{
+    atm::{Atm, HandledTransactionError},
+    common::{Amount, ClientID, TransactionID},
+    transaction::{Transaction, TransactionType},
+};
+
+/// Number of input bytes decoded into one transaction.
+const TRANSACTION_LEN: usize = 7;
+
+/// Decodes a transaction: client, transaction ID, type and a signed amount
+/// in units of the smallest decimal place. Few clients and transaction IDs
+/// are used so that transactions reference each other.
+fn decode(bytes: &[u8]) -> Transaction {
+    use TransactionType::*;
+    let transaction_id = match bytes[1] {
+        0xff => TransactionID::FIRST_INTERNAL,
+        id => TransactionID(u32::from(id % 32)),
+    };
+    let units = i32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]);
+    let amount = Amount::new(f64::from(units) / 10_000.0);
+    let transaction_type = match bytes[2] % 10 {
+        0 => Deposit(amount),
+        1 => Withdrawal(amount),
+        2 => Dispute,
+        3 => Resolve,
+        4 => Chargeback,
+        5 => Reversal,
+        6 => PartialDispute(amount),
+        7 => PartialResolve(amount),
+        8 => PartialChargeback(amount),
+        _ => Close,
+    };
+    Transaction {
+        client_id: ClientID(u16::from(bytes[0] % 8)),
+        transaction_id,
+        transaction_type,
+        currency: None,
+    }
+}
+
+// Feeds arbitrary transaction sequences into the Atm. No transaction may
+// leave an account with an invalid balance.
+fuzz_target!(|data: &[u8]| {
+    let mut atm = Atm::default();
+    for bytes in data.chunks_exact(TRANSACTION_LEN) {
+        let res = atm.handle_transaction(decode(bytes));
+        assert!(
+            !matches!(res, Err(HandledTransactionError::InvalidClientBalance(..))),
+            "{res:?}"
+        );
+    }
+    for snapshot in atm.accounts() {
+        assert_eq!(snapshot.available() + snapshot.held(), snapshot.total());
+    }
+});
@@ -0,0 +1,34 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use toy_atm::accounting::{
+    atm::{Atm, HandledTransactionError},
+    transaction::{self, Transaction, TransactionReader},
+};
+
+// Parses arbitrary CSV input the way partner files are read and handles the
+// parsed transactions. The allocation-free reader has to accept the same
+// transactions as the serde deserialization.
+fuzz_target!(|data: &[u8]| {
+    let debug = |tx: Option<Transaction>| tx.map(|tx| format!("{tx:?}"));
+    let deserialized: Vec<_> = transaction::csv_reader(data)
+        .into_deserialize::<Transaction>()
+        .map(|tx| debug(tx.ok()))
+        .collect();
+    let read: Vec<_> = TransactionReader::new(data)
+        .map(|tx| debug(tx.ok()))
+        .collect();
+    assert_eq!(deserialized, read);
+
+    let mut atm = Atm::default();
+    for tx in TransactionReader::new(data) {
+        let Ok(tx) = tx else {
+            continue;
+        };
+        let res = atm.handle_transaction(tx);
+        assert!(
+            !matches!(res, Err(HandledTransactionError::InvalidClientBalance(..))),
+            "{res:?}"
+        );
+    }
+});
@@ -1,3 +1,15 @@
 pub mod atm;
 pub mod common;
+pub mod fees;
+pub mod fx;
+pub mod history;
+pub mod interest;
+pub mod limits;
+pub mod lock_policy;
+pub mod pipeline;
+pub mod processor;
+#[cfg(test)]
+mod reference;
+pub mod registry;
+pub mod store;
 pub mod transaction;
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
-use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Sub, SubAssign},
+};
 
 /// [ClientID] is a unique identifier for clients.
 #[derive(Debug, Default, Clone, Copy, Eq, Hash, PartialEq, Deserialize, Serialize)]
@@ -7,30 +10,146 @@ pub struct ClientID(pub u16);
 
 /// [TransactionID] is a unique identifier for transactions.
 /// We can assume that the transaction IDs are globaly unique.
-#[derive(Debug, Default, Clone, Copy, Eq, Hash, PartialEq, Deserialize, Serialize)]
+#[derive(
+    Debug, Default, Clone, Copy, Eq, Hash, PartialEq, PartialOrd, Ord, Deserialize, Serialize,
+)]
 pub struct TransactionID(pub u32);
 
 impl TransactionID {
+    /// First [TransactionID] of the namespace reserved for transactions
+    /// generated by the engine. Externally supplied IDs must stay below it.
+    pub const FIRST_INTERNAL: Self = Self(0x8000_0000);
+
     /// Mutates the [TransactionID] by 1.
+    ///
+    /// Panics on overflow, see [TransactionID::checked_next].
     pub fn increase_by_one(&mut self) {
-        self.0 += 1
+        *self = self.next()
     }
 
     /// Returns a new [TransactionID] increased by 1.
+    ///
+    /// Panics on overflow, see [TransactionID::checked_next].
     pub fn next(&self) -> Self {
-        Self(self.0 + 1)
+        self.checked_next().expect("transaction ID overflow")
+    }
+
+    /// Returns a new [TransactionID] increased by 1 or [None] on overflow.
+    pub fn checked_next(&self) -> Option<Self> {
+        self.0.checked_add(1).map(Self)
+    }
+
+    /// Returns true if the ID belongs to the namespace reserved for
+    /// transactions generated by the engine.
+    pub fn is_internal(&self) -> bool {
+        *self >= Self::FIRST_INTERNAL
+    }
+}
+
+/// [TransactionIDAllocator] allocates the [TransactionID]s of transactions
+/// generated by the engine (fees, interest, reversals, adjustments) from the
+/// reserved namespace starting at [TransactionID::FIRST_INTERNAL] so that
+/// they never collide with externally supplied IDs.
+#[derive(Debug, Default, Clone)]
+pub struct TransactionIDAllocator {
+    /// Last allocated [TransactionID].
+    last: Option<TransactionID>,
+}
+
+impl TransactionIDAllocator {
+    /// Allocates the next internal [TransactionID] or returns [None] once the
+    /// namespace is exhausted.
+    pub fn allocate(&mut self) -> Option<TransactionID> {
+        let transaction_id = match self.last {
+            Some(last) => last.checked_next()?,
+            None => TransactionID::FIRST_INTERNAL,
+        };
+        self.last = Some(transaction_id);
+        Some(transaction_id)
+    }
+
+    /// Returns the number of allocated IDs.
+    pub fn allocated(&self) -> u32 {
+        self.last
+            .map_or(0, |last| last.0 - TransactionID::FIRST_INTERNAL.0 + 1)
+    }
+}
+
+/// [Currency] is a three letter (ISO 4217) currency code.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    /// Creates a [Currency] from a three letter code, case insensitive.
+    pub fn new(code: &str) -> Option<Self> {
+        let code: [u8; 3] = code.as_bytes().try_into().ok()?;
+        if !code.iter().all(u8::is_ascii_alphabetic) {
+            return None;
+        }
+        Some(Self(code.map(|c| c.to_ascii_uppercase())))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only ascii letters are accepted by the constructor
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+
+    /// Returns the number of decimal places amounts in this currency are
+    /// rounded to.
+    pub fn decimals(&self) -> u32 {
+        match self.as_str() {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF"
+            | "UGX" | "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            "CLF" | "UYW" => 4,
+            _ => 2,
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
-/// [Amount] represents the credit or debit decimal value with defined
-/// precision [`Amount::AMOUNT_PRECISION_EXP`].
+impl Serialize for Currency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(deserializer: D) -> Result<Currency, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        let code = String::deserialize(deserializer)?;
+        Currency::new(&code)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid currency '{code}'")))
+    }
+}
+
+/// [Amount] represents the credit or debit decimal value. Accounts round the
+/// amounts they book to their precision, [`Currency::decimals`] or
+/// [`Amount::DEFAULT_DECIMALS`] without a currency.
 #[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Serialize)]
 pub struct Amount(f64);
 
 impl Amount {
+    /// Creates an amount dropping floating point noise beyond
+    /// [`Amount::NOISE_DECIMALS`].
     pub fn new(value: f64) -> Self {
-        let rounded = (value * Self::AMOUNT_PRECISION_EXP).round() / Self::AMOUNT_PRECISION_EXP;
-        Self(rounded)
+        Self(value).round_to(Self::NOISE_DECIMALS)
+    }
+
+    /// Creates an amount read from the input, None if the value is not
+    /// finite or its magnitude exceeds [`Amount::MAX`].
+    pub fn try_new(value: f64) -> Option<Self> {
+        (value.is_finite() && value.abs() <= Self::MAX).then(|| Self::new(value))
     }
 
     pub fn reversed(&self) -> Self {
@@ -57,12 +176,28 @@ impl<'de> Deserialize<'de> for Amount {
     {
         // TODO trim precision on the actual string
         let tmp = f64::deserialize(deserializer)?;
-        Ok(Amount::new(tmp))
+        Amount::try_new(tmp)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid amount {tmp}")))
     }
 }
 
 impl Amount {
-    const AMOUNT_PRECISION_EXP: f64 = 1e4;
+    /// Number of decimal places of amounts without a [Currency].
+    pub const DEFAULT_DECIMALS: u32 = 4;
+
+    /// Number of decimal places new amounts are rounded to, to drop floating
+    /// point noise before they are rounded to the precision of an account.
+    const NOISE_DECIMALS: u32 = 8;
+
+    /// Largest magnitude of an amount read from the input, 2^53 units of
+    /// [`Amount::NOISE_DECIMALS`] so that rounding it is exact.
+    pub const MAX: f64 = 90_071_992.547_409_92;
+
+    /// Returns the amount rounded to the given number of decimal places.
+    pub fn round_to(&self, decimals: u32) -> Self {
+        let exp = 10f64.powi(decimals as i32);
+        Self((self.0 * exp).round() / exp)
+    }
 
     /// Check if the amount is negative.
     pub fn is_negative(&self) -> bool {
@@ -75,11 +210,13 @@ impl Amount {
     }
 }
 
+// No account is more precise than [`Amount::DEFAULT_DECIMALS`], rounding
+// sums to them keeps balances exact up to 2^52 of those units, about 4.5e11.
 impl Add for Amount {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
-        (self.0 + rhs.0).into()
+        Self(self.0 + rhs.0).round_to(Self::DEFAULT_DECIMALS)
     }
 }
 
@@ -87,18 +224,52 @@ impl Sub for Amount {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        (self.0 - rhs.0).into()
+        Self(self.0 - rhs.0).round_to(Self::DEFAULT_DECIMALS)
     }
 }
 
 impl AddAssign for Amount {
     fn add_assign(&mut self, rhs: Self) {
-        *self = (self.0 + rhs.0).into();
+        *self = *self + rhs;
     }
 }
 
 impl SubAssign for Amount {
     fn sub_assign(&mut self, rhs: Self) {
-        *self = (self.0 - rhs.0).into();
+        *self = *self - rhs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TransactionID, TransactionIDAllocator};
+
+    #[test]
+    fn test_transaction_id_allocator() {
+        let mut allocator = TransactionIDAllocator::default();
+        assert_eq!(allocator.allocate(), Some(TransactionID::FIRST_INTERNAL));
+        assert_eq!(allocator.allocate(), Some(TransactionID(0x8000_0001)));
+        assert_eq!(allocator.allocated(), 2);
+        assert!(TransactionID(0x8000_0001).is_internal());
+        assert!(!TransactionID(0x7fff_ffff).is_internal());
+        assert_eq!(TransactionID(u32::MAX).checked_next(), None);
+        assert_eq!(
+            TransactionID(0x7fff_ffff).next(),
+            TransactionID::FIRST_INTERNAL
+        );
+
+        let mut allocator = TransactionIDAllocator {
+            last: Some(TransactionID(u32::MAX - 1)),
+        };
+        assert_eq!(allocator.allocate(), Some(TransactionID(u32::MAX)));
+        assert_eq!(allocator.allocate(), None, "namespace exhausted");
+        assert_eq!(allocator.allocate(), None);
+        assert_eq!(allocator.allocated(), 0x8000_0000);
+    }
+
+    #[test]
+    #[should_panic(expected = "transaction ID overflow")]
+    fn test_transaction_id_next_overflow() {
+        TransactionID(u32::MAX).next();
     }
 }
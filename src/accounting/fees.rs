@@ -0,0 +1,312 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use super::common::{Amount, Currency};
+
+/// [Fee] defines how the fee for a transaction amount is calculated.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fee {
+    /// [Fee::Flat] charges the same amount for every transaction.
+    Flat(Amount),
+
+    /// [Fee::Percentage] charges a percentage of the transaction amount.
+    Percentage(f64),
+
+    /// [Fee::Tiered] charges the fee of the first tier the transaction amount
+    /// fits into. Amounts larger than all tiers are not charged.
+    Tiered(Vec<FeeTier>),
+}
+
+/// [FeeTier] applies its fee to transaction amounts up to and including
+/// `up_to`. A missing `up_to` matches any amount.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeTier {
+    pub up_to: Option<Amount>,
+
+    pub fee: Fee,
+}
+
+impl Fee {
+    /// Calculates the fee for the given transaction amount.
+    pub fn calc(&self, amount: Amount) -> Amount {
+        match self {
+            Self::Flat(fee) => *fee,
+            Self::Percentage(percentage) => Amount::new(f64::from(amount) * percentage / 100.0),
+            Self::Tiered(tiers) => tiers
+                .iter()
+                .find(|tier| tier.up_to.is_none_or(|up_to| amount <= up_to))
+                .map(|tier| tier.fee.calc(amount))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Checks that the fee is finite and not negative and that the tiers
+    /// are in strictly increasing order, only the last one without `up_to`.
+    fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Flat(fee) => {
+                let fee = f64::from(*fee);
+                if !fee.is_finite() || fee < 0.0 {
+                    bail!("invalid flat fee {fee}");
+                }
+            }
+            Self::Percentage(percentage) => {
+                if !percentage.is_finite() || *percentage < 0.0 {
+                    bail!("invalid fee percentage {percentage}");
+                }
+            }
+            Self::Tiered(tiers) => {
+                let mut previous = None;
+                for (idx, tier) in tiers.iter().enumerate() {
+                    tier.fee.validate()?;
+                    match tier.up_to {
+                        None if idx + 1 < tiers.len() => {
+                            bail!("only the last fee tier can omit 'up_to'")
+                        }
+                        Some(up_to) if !f64::from(up_to).is_finite() => {
+                            bail!("invalid fee tier 'up_to' {}", f64::from(up_to))
+                        }
+                        Some(up_to) if previous.is_some_and(|previous| up_to <= previous) => {
+                            bail!("fee tiers must be in strictly increasing order of 'up_to'")
+                        }
+                        _ => {}
+                    }
+                    previous = tier.up_to;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [FeeKind] states which kind of transaction a fee was charged for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// [FeeSchedule] defines the fees per transaction type. A missing fee means
+/// that the transaction type is free of charge.
+///
+/// It can be loaded from a TOML file:
+///
+/// ```toml
+/// [deposit]
+/// percentage = 0.5
+///
+/// [withdrawal]
+/// tiered = [
+///     { up_to = 100.0, fee = { flat = 1.0 } },
+///     { fee = { percentage = 1.0 } },
+/// ]
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeSchedule {
+    pub deposit: Option<Fee>,
+
+    pub withdrawal: Option<Fee>,
+}
+
+impl FeeSchedule {
+    pub fn from_toml_str(input: &str) -> anyhow::Result<Self> {
+        let schedule: Self = toml::from_str(input)?;
+        schedule.validate()?;
+        Ok(schedule)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_toml_str(&std::fs::read_to_string(path)?)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for fee in self.deposit.iter().chain(&self.withdrawal) {
+            fee.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Calculates the fee for a transaction of the given kind and amount.
+    /// Negative fees are never charged.
+    pub fn fee_for(&self, kind: FeeKind, amount: Amount) -> Amount {
+        let fee = match kind {
+            FeeKind::Deposit => &self.deposit,
+            FeeKind::Withdrawal => &self.withdrawal,
+        };
+        match fee.as_ref().map(|fee| fee.calc(amount)) {
+            Some(fee) if !fee.is_negative() => fee,
+            _ => Amount::default(),
+        }
+    }
+}
+
+/// [FeeTotals] sums up the fees of a single [FeeKind].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FeeTotals {
+    /// Number of fees charged.
+    pub count: u64,
+
+    /// Sum of all fees charged.
+    pub charged: Amount,
+
+    /// Sum of the fees refunded through a chargeback or reversal.
+    pub refunded: Amount,
+
+    /// Fee revenue, charged minus refunded.
+    pub revenue: Amount,
+}
+
+impl FeeTotals {
+    pub fn add(&mut self, amount: Amount, refunded: Amount) {
+        self.count += 1;
+        self.charged += amount;
+        self.refunded += refunded;
+        self.revenue = self.charged - self.refunded;
+    }
+}
+
+/// [FeeReportRow] is a single output row of the [FeeReport].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FeeReportRow {
+    #[serde(rename = "fee")]
+    pub kind: FeeKind,
+
+    pub currency: Option<Currency>,
+
+    pub count: u64,
+
+    pub charged: Amount,
+
+    pub refunded: Amount,
+
+    pub revenue: Amount,
+}
+
+/// [FeeReport] holds the fee revenue totals per [FeeKind] and [Currency].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FeeReport {
+    totals: BTreeMap<(FeeKind, Option<Currency>), FeeTotals>,
+}
+
+impl FeeReport {
+    pub fn totals_mut(&mut self, kind: FeeKind, currency: Option<Currency>) -> &mut FeeTotals {
+        self.totals.entry((kind, currency)).or_default()
+    }
+
+    pub fn totals(&self, kind: FeeKind, currency: Option<Currency>) -> FeeTotals {
+        self.totals
+            .get(&(kind, currency))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns the output rows ordered by [FeeKind] and [Currency].
+    pub fn rows(&self) -> impl Iterator<Item = FeeReportRow> + '_ {
+        self.totals
+            .iter()
+            .map(|(&(kind, currency), totals)| FeeReportRow {
+                kind,
+                currency,
+                count: totals.count,
+                charged: totals.charged,
+                refunded: totals.refunded,
+                revenue: totals.revenue,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fee, FeeKind, FeeSchedule, FeeTier};
+    use crate::accounting::common::Amount;
+
+    #[test]
+    fn test_fee_schedule_from_toml() {
+        let fee_schedule = FeeSchedule::from_toml_str(
+            r#"
+            [deposit]
+            percentage = 0.5
+
+            [withdrawal]
+            tiered = [
+                { up_to = 100.0, fee = { flat = 1.0 } },
+                { up_to = 1000.0, fee = { percentage = 1.0 } },
+            ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(fee_schedule.deposit, Some(Fee::Percentage(0.5)));
+        assert_eq!(
+            fee_schedule.withdrawal,
+            Some(Fee::Tiered(vec![
+                FeeTier {
+                    up_to: Some(Amount::new(100.0)),
+                    fee: Fee::Flat(Amount::new(1.0)),
+                },
+                FeeTier {
+                    up_to: Some(Amount::new(1000.0)),
+                    fee: Fee::Percentage(1.0),
+                },
+            ]))
+        );
+
+        let fee_for = |kind, amount| fee_schedule.fee_for(kind, Amount::new(amount));
+        assert_eq!(fee_for(FeeKind::Deposit, 200.0), Amount::new(1.0));
+        assert_eq!(fee_for(FeeKind::Withdrawal, 100.0), Amount::new(1.0));
+        assert_eq!(fee_for(FeeKind::Withdrawal, 500.0), Amount::new(5.0));
+        assert!(fee_for(FeeKind::Withdrawal, 5000.0).is_zero());
+    }
+
+    #[test]
+    fn test_negative_fee_is_not_charged() {
+        let fee_schedule = FeeSchedule {
+            deposit: Some(Fee::Flat(Amount::new(-1.0))),
+            withdrawal: None,
+        };
+        assert!(fee_schedule
+            .fee_for(FeeKind::Deposit, Amount::new(10.0))
+            .is_zero());
+        assert!(fee_schedule
+            .fee_for(FeeKind::Withdrawal, Amount::new(10.0))
+            .is_zero());
+    }
+
+    #[test]
+    fn test_invalid_fee_schedule() {
+        for (toml, error) in [
+            ("[deposit]\npercentage = nan", "invalid fee percentage NaN"),
+            (
+                "[deposit]\npercentage = -0.5",
+                "invalid fee percentage -0.5",
+            ),
+            ("[withdrawal]\nflat = -1.0", "invalid flat fee -1"),
+            (
+                "[withdrawal]\ntiered = [{ up_to = 10.0, fee = { flat = -1.0 } }]",
+                "invalid flat fee -1",
+            ),
+            (
+                "[withdrawal]\ntiered = [{ up_to = 10.0, fee = { flat = 1.0 } }, \
+                 { up_to = 10.0, fee = { flat = 2.0 } }]",
+                "fee tiers must be in strictly increasing order of 'up_to'",
+            ),
+            (
+                "[withdrawal]\ntiered = [{ fee = { flat = 1.0 } }, \
+                 { up_to = 10.0, fee = { flat = 2.0 } }]",
+                "only the last fee tier can omit 'up_to'",
+            ),
+        ] {
+            let err = FeeSchedule::from_toml_str(toml).unwrap_err();
+            assert_eq!(err.to_string(), error, "{toml}");
+        }
+
+        // amounts are rejected while they are read
+        let err = FeeSchedule::from_toml_str("[withdrawal]\nflat = inf").unwrap_err();
+        assert!(err.to_string().contains("invalid amount inf"), "{err}");
+    }
+}
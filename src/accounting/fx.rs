@@ -0,0 +1,252 @@
+use std::{cmp::Ordering, collections::HashMap, fmt, io::Read, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail};
+use csv::Trim;
+use serde::Deserialize;
+
+use super::common::{Amount, Currency};
+
+/// [ExchangeRate] is an exact decimal exchange rate stored as an integer
+/// mantissa and the number of decimal places (scale).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExchangeRate {
+    mantissa: u64,
+    scale: u32,
+}
+
+impl ExchangeRate {
+    /// Maximum number of decimal places of an exchange rate.
+    pub const MAX_SCALE: u32 = 12;
+
+    /// Converts the amount with `from_decimals` decimal places with exact
+    /// decimal math and rounds the result half away from zero to the given
+    /// number of decimal places. Returns None if the conversion overflows.
+    pub fn convert(&self, amount: Amount, from_decimals: u32, decimals: u32) -> Option<Amount> {
+        let units = f64::from(amount) * 10f64.powi(from_decimals as i32);
+        // beyond 2^63 the units are neither exact nor fit the integer math
+        if !units.is_finite() || units.abs() >= 2f64.powi(63) {
+            return None;
+        }
+        let units = units.round() as i128;
+        let mut numerator = units.checked_mul(self.mantissa as i128)?;
+        let mut denominator = 10i128.checked_pow(self.scale)?;
+        match decimals.cmp(&from_decimals) {
+            Ordering::Less => {
+                denominator =
+                    denominator.checked_mul(10i128.checked_pow(from_decimals - decimals)?)?
+            }
+            Ordering::Greater => {
+                numerator = numerator.checked_mul(10i128.checked_pow(decimals - from_decimals)?)?
+            }
+            Ordering::Equal => {}
+        }
+        let rounded = (numerator.abs() + denominator / 2) / denominator * numerator.signum();
+        Some(Amount::new(rounded as f64 / 10f64.powi(decimals as i32)))
+    }
+}
+
+impl FromStr for ExchangeRate {
+    type Err = anyhow::Error;
+
+    fn from_str(rate: &str) -> Result<Self, Self::Err> {
+        let (int, frac) = rate.split_once('.').unwrap_or((rate, ""));
+        let frac = frac.trim_end_matches('0');
+        if (int.is_empty() && frac.is_empty())
+            || !int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+        {
+            bail!("invalid exchange rate '{rate}'");
+        }
+        if frac.len() > Self::MAX_SCALE as usize {
+            bail!(
+                "exchange rate '{rate}' has more than {} decimal places",
+                Self::MAX_SCALE
+            );
+        }
+        let mantissa = format!("{int}{frac}")
+            .parse::<u64>()
+            .map_err(|_| anyhow!("exchange rate '{rate}' out of range"))?;
+        if mantissa == 0 {
+            bail!("exchange rate must not be zero");
+        }
+        Ok(Self {
+            mantissa,
+            scale: frac.len() as u32,
+        })
+    }
+}
+
+impl fmt::Display for ExchangeRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exp = 10u64.pow(self.scale);
+        let (int, frac) = (self.mantissa / exp, self.mantissa % exp);
+        if self.scale == 0 {
+            write!(f, "{int}")
+        } else {
+            write!(f, "{int}.{frac:0width$}", width = self.scale as usize)
+        }
+    }
+}
+
+/// [Conversion] holds the details of a withdrawal that was converted from
+/// the currency it was made in to the currency of the debited account.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conversion {
+    /// Currency the withdrawal was made in.
+    pub currency: Currency,
+
+    /// Withdrawal amount in [Conversion::currency].
+    pub amount: Amount,
+
+    /// Exchange rate used for the conversion.
+    pub rate: ExchangeRate,
+}
+
+/// [FxRateRecord] is used as a deserialization helper struct for the FX rate
+/// table ONLY.
+#[derive(Debug, Deserialize)]
+struct FxRateRecord {
+    from: Currency,
+    to: Currency,
+    rate: String,
+    #[serde(default)]
+    effective_from: Option<u64>,
+}
+
+/// [FxRates] is a table of exchange rates per currency pair, versioned by the
+/// sequence number from which on a rate is effective.
+///
+/// It can be loaded from a CSV file:
+///
+/// ```csv
+/// from,to,rate,effective_from
+/// EUR,USD,1.0825,0
+/// EUR,USD,1.0850,1000
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FxRates {
+    /// Rates per (from, to) pair ordered by ascending effective sequence.
+    rates: HashMap<(Currency, Currency), Vec<(u64, ExchangeRate)>>,
+}
+
+impl FxRates {
+    /// Adds a rate converting amounts in `from` into `to` effective from the
+    /// given sequence number on.
+    pub fn with_rate(
+        mut self,
+        from: Currency,
+        to: Currency,
+        rate: ExchangeRate,
+        effective_from: u64,
+    ) -> Self {
+        let versions = self.rates.entry((from, to)).or_default();
+        let idx = versions.partition_point(|(effective, _)| *effective <= effective_from);
+        if idx > 0 && versions[idx - 1].0 == effective_from {
+            versions[idx - 1].1 = rate;
+        } else {
+            versions.insert(idx, (effective_from, rate));
+        }
+        self
+    }
+
+    pub fn from_reader(rdr: impl Read) -> anyhow::Result<Self> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(rdr);
+        let mut fx_rates = Self::default();
+        for record in rdr.deserialize::<FxRateRecord>() {
+            let record = record?;
+            if record.from == record.to {
+                bail!("exchange rate from {} to itself", record.from);
+            }
+            let rate = record.rate.parse()?;
+            fx_rates = fx_rates.with_rate(
+                record.from,
+                record.to,
+                rate,
+                record.effective_from.unwrap_or_default(),
+            );
+        }
+        Ok(fx_rates)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_reader(std::fs::File::open(path)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    /// Returns the rate converting `from` into `to` effective at the given
+    /// sequence number.
+    pub fn rate(&self, from: Currency, to: Currency, sequence: u64) -> Option<ExchangeRate> {
+        let versions = self.rates.get(&(from, to))?;
+        let idx = versions.partition_point(|(effective, _)| *effective <= sequence);
+        idx.checked_sub(1).map(|idx| versions[idx].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExchangeRate, FxRates};
+    use crate::accounting::common::{Amount, Currency};
+
+    #[test]
+    fn test_exchange_rate_parse_and_convert() {
+        let rate: ExchangeRate = "1.0825".parse().unwrap();
+        assert_eq!(rate.to_string(), "1.0825");
+        assert_eq!(
+            rate.convert(Amount::new(100.0), 2, 2),
+            Some(Amount::new(108.25))
+        );
+        assert_eq!(
+            rate.convert(Amount::new(0.1), 4, 4),
+            Some(Amount::new(0.1083))
+        );
+        assert_eq!(
+            rate.convert(Amount::new(0.1), 4, 2),
+            Some(Amount::new(0.11))
+        );
+
+        let rate: ExchangeRate = "151.370000".parse().unwrap();
+        assert_eq!(rate.to_string(), "151.37");
+        assert_eq!(
+            rate.convert(Amount::new(10.0), 2, 0),
+            Some(Amount::new(1514.0))
+        );
+
+        let rate: ExchangeRate = "18446744.073709551615".parse().unwrap();
+        assert_eq!(rate.convert(Amount::new(9e18), 0, 4), None, "overflows");
+        assert_eq!(rate.convert(Amount::new(1e300), 2, 2), None, "out of range");
+
+        for invalid in ["", ".", "abc", "-1.0", "0.0", "1.0000000000001"] {
+            assert!(invalid.parse::<ExchangeRate>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_fx_rates_versioned_by_sequence() {
+        let fx_rates = FxRates::from_reader(
+            "from, to, rate, effective_from\n\
+             eur, usd, 1.08, 0\n\
+             eur, usd, 1.10, 100\n\
+             usd, jpy, 150,\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let eur = Currency::new("EUR").unwrap();
+        let usd = Currency::new("USD").unwrap();
+        let jpy = Currency::new("JPY").unwrap();
+        assert_eq!(fx_rates.rate(eur, usd, 99), Some("1.08".parse().unwrap()));
+        assert_eq!(fx_rates.rate(eur, usd, 100), Some("1.1".parse().unwrap()));
+        assert_eq!(fx_rates.rate(usd, jpy, 5), Some("150".parse().unwrap()));
+        assert_eq!(
+            fx_rates.rate(usd, eur, 5),
+            None,
+            "inverse pairs are not implied"
+        );
+
+        assert!(FxRates::from_reader("from,to,rate\neur,eur,1\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use super::{
+    atm::ClientBalanceSnapshot,
+    common::{ClientID, Currency},
+};
+
+/// [BalanceHistory] keeps a [ClientBalanceSnapshot] checkpoint for every
+/// sequence number that changed the balance of a single client account. Since ignored
+/// transactions never modify the account balance, the balance after any
+/// sequence number is the last checkpoint at or before it.
+#[derive(Debug)]
+pub struct BalanceHistory {
+    /// Sequence number of the first transaction seen for the client.
+    opened_at: u64,
+
+    /// Checkpoints ordered by ascending sequence number.
+    checkpoints: Vec<(u64, ClientBalanceSnapshot)>,
+}
+
+impl BalanceHistory {
+    pub fn new(opened_at: u64) -> Self {
+        Self {
+            opened_at,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Records the balance after the transaction with the given sequence number.
+    /// Recording the same sequence number again replaces its checkpoint.
+    pub fn record(&mut self, sequence: u64, snapshot: ClientBalanceSnapshot) {
+        if let Some((last, last_snapshot)) = self.checkpoints.last_mut() {
+            debug_assert!(
+                *last <= sequence,
+                "checkpoints must be recorded in ascending sequence order"
+            );
+            if *last == sequence {
+                *last_snapshot = snapshot;
+                return;
+            }
+        }
+        self.checkpoints.push((sequence, snapshot));
+    }
+
+    /// Returns the balance after the transaction with the given sequence
+    /// number or [None] if the client did not exist at that point.
+    pub fn balance_at(
+        &self,
+        client_id: ClientID,
+        currency: Option<Currency>,
+        sequence: u64,
+    ) -> Option<ClientBalanceSnapshot> {
+        if sequence < self.opened_at {
+            return None;
+        }
+        let idx = self
+            .checkpoints
+            .partition_point(|(checkpoint, _)| *checkpoint <= sequence);
+        match idx {
+            0 => Some(ClientBalanceSnapshot::empty(client_id, currency)),
+            idx => Some(self.checkpoints[idx - 1].1.clone()),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::path::Path;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use super::common::{Amount, ClientID, Currency, TransactionID};
+
+/// [InterestTier] applies its rate to available amounts up to and including
+/// `up_to`. A missing `up_to` matches any amount.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterestTier {
+    pub up_to: Option<Amount>,
+
+    /// Interest rate in percent per accrual.
+    pub rate: f64,
+}
+
+/// [InterestSchedule] defines the interest credited on available amounts.
+/// The whole available amount earns the rate of the first tier it fits
+/// into. Amounts larger than all tiers and negative amounts earn nothing.
+///
+/// It can be loaded from a TOML file:
+///
+/// ```toml
+/// # accrue after every 1000 input transactions
+/// period = 1000
+///
+/// tiers = [
+///     { up_to = 1000.0, rate = 0.01 },
+///     { rate = 0.02 },
+/// ]
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterestSchedule {
+    /// Number of input transactions between two accruals. The input has no
+    /// timestamps so a period is a fixed number of consecutive input
+    /// transactions. Interest is only accrued on demand if missing.
+    pub period: Option<u64>,
+
+    #[serde(default)]
+    pub tiers: Vec<InterestTier>,
+}
+
+impl InterestSchedule {
+    pub fn from_toml_str(input: &str) -> anyhow::Result<Self> {
+        let schedule: Self = toml::from_str(input)?;
+        schedule.validate()?;
+        Ok(schedule)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_toml_str(&std::fs::read_to_string(path)?)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.period == Some(0) {
+            bail!("interest 'period' must be positive");
+        }
+        if let Some(tier) = self
+            .tiers
+            .iter()
+            .find(|tier| !tier.rate.is_finite() || tier.rate < 0.0)
+        {
+            bail!("invalid interest rate {}", tier.rate);
+        }
+        Ok(())
+    }
+
+    /// Returns true if interest is due after the transaction with the given
+    /// sequence number.
+    pub fn is_due(&self, sequence: u64) -> bool {
+        self.period
+            .is_some_and(|period| period > 0 && sequence.is_multiple_of(period))
+    }
+
+    /// Calculates the interest for the given available amount.
+    pub fn interest_for(&self, available: Amount) -> Amount {
+        if available.is_negative() {
+            return Amount::default();
+        }
+        self.tiers
+            .iter()
+            .find(|tier| tier.up_to.is_none_or(|up_to| available <= up_to))
+            .map(|tier| Amount::new(f64::from(available) * tier.rate / 100.0))
+            .unwrap_or_default()
+    }
+}
+
+/// [InterestEntry] describes an interest credit posted by an accrual.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct InterestEntry {
+    #[serde(rename = "client")]
+    pub client_id: ClientID,
+
+    pub currency: Option<Currency>,
+
+    /// Internal [TransactionID] of the credit which can be used to dispute
+    /// and reverse it.
+    #[serde(rename = "tx")]
+    pub transaction_id: TransactionID,
+
+    pub amount: Amount,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InterestSchedule, InterestTier};
+    use crate::accounting::common::Amount;
+
+    #[test]
+    fn test_interest_schedule_from_toml() {
+        let schedule = InterestSchedule::from_toml_str(
+            r#"
+            period = 1000
+
+            tiers = [
+                { up_to = 1000.0, rate = 0.5 },
+                { up_to = 10000.0, rate = 1.0 },
+            ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(schedule.period, Some(1000));
+        assert_eq!(
+            schedule.tiers[0],
+            InterestTier {
+                up_to: Some(Amount::new(1000.0)),
+                rate: 0.5,
+            }
+        );
+        assert!(!schedule.is_due(999));
+        assert!(schedule.is_due(2000));
+
+        let interest_for = |available| schedule.interest_for(Amount::new(available));
+        assert_eq!(interest_for(200.0), Amount::new(1.0));
+        assert_eq!(interest_for(2000.0), Amount::new(20.0));
+        assert!(interest_for(20000.0).is_zero());
+        assert!(interest_for(-200.0).is_zero());
+
+        for invalid in ["period = 0", "tiers = [{ rate = -1.0 }]", "rate = 1.0"] {
+            assert!(
+                InterestSchedule::from_toml_str(invalid).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+
+use super::common::{Amount, ClientID};
+
+/// [Limits] defines the maximum amounts allowed for deposits or withdrawals.
+/// A missing value means that there is no limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum amount of a single transaction.
+    pub per_transaction: Option<Amount>,
+
+    /// Maximum accepted amount within a single daily window.
+    pub daily: Option<Amount>,
+
+    /// Maximum accepted amount over the lifetime of the account.
+    pub lifetime: Option<Amount>,
+}
+
+impl Limits {
+    fn has_daily(&self) -> bool {
+        self.daily.is_some()
+    }
+}
+
+/// [ClientLimits] holds the deposit and withdrawal [Limits] of a client or
+/// client tier.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientLimits {
+    #[serde(default)]
+    pub deposit: Limits,
+
+    #[serde(default)]
+    pub withdrawal: Limits,
+
+    /// Overdraft facility allowing the available amount to go negative down
+    /// to minus this amount through withdrawals.
+    pub overdraft: Option<Amount>,
+
+    /// Length of the daily window in sequence numbers. The input has no
+    /// timestamps so a "day" is a fixed number of consecutive input
+    /// transactions. Set from [LimitsConfig::window].
+    #[serde(skip)]
+    pub window: Option<u64>,
+}
+
+/// [LimitUsage] tracks the accepted deposit and withdrawal amounts a client
+/// has used up against its [ClientLimits].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LimitUsage {
+    /// Index of the daily window the daily amounts belong to.
+    window_index: u64,
+
+    deposited_daily: Amount,
+    withdrawn_daily: Amount,
+
+    deposited_lifetime: Amount,
+    withdrawn_lifetime: Amount,
+}
+
+/// [LimitExceeded] states which limit would be exceeded by a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    PerTransaction,
+    Daily,
+    Lifetime,
+}
+
+impl ClientLimits {
+    fn window_index(&self, sequence: u64) -> u64 {
+        match self.window {
+            Some(window) if window > 0 => sequence.saturating_sub(1) / window,
+            _ => 0,
+        }
+    }
+
+    /// Checks if a deposit or withdrawal of the given amount at the given
+    /// sequence number stays within the limits.
+    pub fn check(
+        &self,
+        usage: &LimitUsage,
+        sequence: u64,
+        amount: Amount,
+        is_withdrawal: bool,
+    ) -> Result<(), LimitExceeded> {
+        let (limits, daily, lifetime) = if is_withdrawal {
+            (
+                &self.withdrawal,
+                usage.withdrawn_daily,
+                usage.withdrawn_lifetime,
+            )
+        } else {
+            (
+                &self.deposit,
+                usage.deposited_daily,
+                usage.deposited_lifetime,
+            )
+        };
+        let daily = if usage.window_index == self.window_index(sequence) {
+            daily
+        } else {
+            Amount::default()
+        };
+
+        if limits.per_transaction.is_some_and(|limit| amount > limit) {
+            return Err(LimitExceeded::PerTransaction);
+        }
+        if limits.daily.is_some_and(|limit| daily + amount > limit) {
+            return Err(LimitExceeded::Daily);
+        }
+        if limits
+            .lifetime
+            .is_some_and(|limit| lifetime + amount > limit)
+        {
+            return Err(LimitExceeded::Lifetime);
+        }
+        Ok(())
+    }
+
+    /// Books an accepted deposit or withdrawal against the usage.
+    pub fn record(
+        &self,
+        usage: &mut LimitUsage,
+        sequence: u64,
+        amount: Amount,
+        is_withdrawal: bool,
+    ) {
+        let window_index = self.window_index(sequence);
+        if usage.window_index != window_index {
+            usage.window_index = window_index;
+            usage.deposited_daily = Amount::default();
+            usage.withdrawn_daily = Amount::default();
+        }
+        if is_withdrawal {
+            usage.withdrawn_daily += amount;
+            usage.withdrawn_lifetime += amount;
+        } else {
+            usage.deposited_daily += amount;
+            usage.deposited_lifetime += amount;
+        }
+    }
+}
+
+/// [LimitsConfigFile] is used as a deserialization helper struct for the
+/// TOML limits file ONLY.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitsConfigFile {
+    window: Option<u64>,
+
+    #[serde(default)]
+    default: ClientLimits,
+
+    #[serde(default)]
+    tiers: HashMap<String, ClientLimits>,
+
+    /// Maps client IDs to tier names.
+    #[serde(default)]
+    clients: HashMap<String, String>,
+
+    /// Client specific limits taking precedence over tiers.
+    #[serde(default)]
+    overrides: HashMap<String, ClientLimits>,
+}
+
+/// [LimitsConfig] resolves the [ClientLimits] of every client. Client
+/// specific overrides take precedence over the client tier which takes
+/// precedence over the default limits.
+///
+/// It can be loaded from a TOML file:
+///
+/// ```toml
+/// # length of the daily window in input transactions
+/// window = 1000
+///
+/// [default.withdrawal]
+/// per_transaction = 500.0
+/// daily = 1000.0
+///
+/// [tiers.gold.withdrawal]
+/// daily = 10000.0
+///
+/// [clients]
+/// 7 = "gold"
+///
+/// [overrides.8]
+/// overdraft = 50.0
+///
+/// [overrides.8.deposit]
+/// lifetime = 100.0
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LimitsConfig {
+    window: Option<u64>,
+    default: ClientLimits,
+    tiers: HashMap<String, ClientLimits>,
+    clients: HashMap<ClientID, String>,
+    overrides: HashMap<ClientID, ClientLimits>,
+}
+
+impl LimitsConfig {
+    /// Creates a config applying the same limits to all clients.
+    pub fn new(default: ClientLimits, window: Option<u64>) -> Self {
+        Self {
+            window,
+            default,
+            ..Default::default()
+        }
+    }
+
+    /// Defines limits for a client tier.
+    pub fn with_tier(mut self, tier: &str, limits: ClientLimits) -> Self {
+        self.tiers.insert(tier.to_owned(), limits);
+        self
+    }
+
+    /// Assigns a client to a client tier.
+    pub fn with_client_tier(mut self, client_id: ClientID, tier: &str) -> Self {
+        self.clients.insert(client_id, tier.to_owned());
+        self
+    }
+
+    /// Defines client specific limits.
+    pub fn with_client_limits(mut self, client_id: ClientID, limits: ClientLimits) -> Self {
+        self.overrides.insert(client_id, limits);
+        self
+    }
+
+    pub fn from_toml_str(input: &str) -> anyhow::Result<Self> {
+        let file: LimitsConfigFile = toml::from_str(input)?;
+        let parse_client_id = |key: &str| {
+            key.parse::<u16>()
+                .map(ClientID)
+                .map_err(|_| anyhow!("invalid client id '{key}'"))
+        };
+        let mut clients = HashMap::new();
+        for (client, tier) in file.clients {
+            if !file.tiers.contains_key(&tier) {
+                bail!("client {client} references unknown tier '{tier}'");
+            }
+            clients.insert(parse_client_id(&client)?, tier);
+        }
+        let mut overrides = HashMap::new();
+        for (client, limits) in file.overrides {
+            overrides.insert(parse_client_id(&client)?, limits);
+        }
+        let config = Self {
+            window: file.window,
+            default: file.default,
+            tiers: file.tiers,
+            clients,
+            overrides,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_toml_str(&std::fs::read_to_string(path)?)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let has_daily = std::iter::once(&self.default)
+            .chain(self.tiers.values())
+            .chain(self.overrides.values())
+            .any(|limits| limits.deposit.has_daily() || limits.withdrawal.has_daily());
+        if has_daily && self.window.is_none_or(|window| window == 0) {
+            bail!("daily limits require a positive 'window'");
+        }
+        let negative_overdraft = std::iter::once(&self.default)
+            .chain(self.tiers.values())
+            .chain(self.overrides.values())
+            .filter_map(|limits| limits.overdraft)
+            .find(|overdraft| overdraft.is_negative());
+        if let Some(overdraft) = negative_overdraft {
+            bail!("negative 'overdraft' {}", f64::from(overdraft));
+        }
+        Ok(())
+    }
+
+    /// Returns the resolved limits for the given client.
+    pub fn client_limits(&self, client_id: ClientID) -> ClientLimits {
+        let limits = self
+            .overrides
+            .get(&client_id)
+            .or_else(|| {
+                self.clients
+                    .get(&client_id)
+                    .and_then(|tier| self.tiers.get(tier))
+            })
+            .unwrap_or(&self.default);
+        ClientLimits {
+            window: self.window,
+            ..*limits
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientLimits, Limits, LimitsConfig};
+    use crate::accounting::common::{Amount, ClientID};
+
+    #[test]
+    fn test_limits_config_from_toml() {
+        let config = LimitsConfig::from_toml_str(
+            r#"
+            window = 1000
+
+            [default.withdrawal]
+            per_transaction = 500.0
+            daily = 1000.0
+
+            [tiers.gold.withdrawal]
+            daily = 10000.0
+
+            [clients]
+            7 = "gold"
+
+            [overrides.8]
+            overdraft = 50.0
+
+            [overrides.8.deposit]
+            lifetime = 100.0
+            "#,
+        )
+        .unwrap();
+
+        let default = config.client_limits(ClientID(1));
+        assert_eq!(default.window, Some(1000));
+        assert_eq!(
+            default.withdrawal,
+            Limits {
+                per_transaction: Some(Amount::new(500.0)),
+                daily: Some(Amount::new(1000.0)),
+                lifetime: None,
+            }
+        );
+        let gold = config.client_limits(ClientID(7));
+        assert_eq!(gold.withdrawal.daily, Some(Amount::new(10000.0)));
+        assert_eq!(gold.withdrawal.per_transaction, None);
+        let overridden = config.client_limits(ClientID(8));
+        assert_eq!(
+            overridden,
+            ClientLimits {
+                deposit: Limits {
+                    lifetime: Some(Amount::new(100.0)),
+                    ..Default::default()
+                },
+                overdraft: Some(Amount::new(50.0)),
+                window: Some(1000),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_limits_config() {
+        let invalid = [
+            "[default.deposit]\ndaily = 1.0",
+            "window = 10\n[clients]\n7 = \"missing\"",
+            "[overrides.client.deposit]\nlifetime = 1.0",
+            "[default.deposit]\nmonthly = 1.0",
+            "[overrides.8]\noverdraft = -50.0",
+            "[tiers.gold]\noverdraft = -0.01",
+        ];
+        for input in invalid {
+            assert!(LimitsConfig::from_toml_str(input).is_err(), "{input}");
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail};
+
+use super::common::Amount;
+
+/// [LockPolicy] decides when chargebacks lock the account of a client.
+/// Chargebacks of fees and interest credits never count towards it.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LockPolicy {
+    /// [LockPolicy::FirstChargeback] locks the account on the first
+    /// chargeback.
+    #[default]
+    FirstChargeback,
+
+    /// [LockPolicy::AfterChargebacks] locks the account once it has seen the
+    /// given number of chargebacks.
+    AfterChargebacks(u32),
+
+    /// [LockPolicy::ChargebackRatio] locks the account once the charged back
+    /// amount exceeds the given ratio of the deposited amount.
+    ChargebackRatio(f64),
+
+    /// [LockPolicy::Never] never locks the account.
+    Never,
+}
+
+impl LockPolicy {
+    /// Returns true if an account with the given chargeback history has to be
+    /// locked.
+    pub fn should_lock(&self, chargebacks: u32, charged_back: Amount, deposited: Amount) -> bool {
+        match *self {
+            Self::FirstChargeback => chargebacks > 0,
+            Self::AfterChargebacks(n) => chargebacks >= n,
+            Self::ChargebackRatio(ratio) => {
+                chargebacks > 0 && f64::from(charged_back) > f64::from(deposited) * ratio
+            }
+            Self::Never => false,
+        }
+    }
+}
+
+impl FromStr for LockPolicy {
+    type Err = anyhow::Error;
+
+    /// Parses `first`, `after:<N>`, `ratio:<R>` or `never`.
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match policy.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (policy, None),
+        };
+        let invalid = || anyhow!("invalid lock policy '{policy}'");
+        let policy = match (name, arg) {
+            ("first", None) => Self::FirstChargeback,
+            ("after", Some(n)) => match n.parse().map_err(|_| invalid())? {
+                0 => bail!("lock policy 'after' requires at least 1 chargeback"),
+                n => Self::AfterChargebacks(n),
+            },
+            ("ratio", Some(ratio)) => {
+                let ratio: f64 = ratio.parse().map_err(|_| invalid())?;
+                if !ratio.is_finite() || ratio < 0.0 {
+                    bail!("lock policy 'ratio' must not be negative");
+                }
+                Self::ChargebackRatio(ratio)
+            }
+            ("never", None) => Self::Never,
+            _ => return Err(invalid()),
+        };
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for LockPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FirstChargeback => write!(f, "first"),
+            Self::AfterChargebacks(n) => write!(f, "after:{n}"),
+            Self::ChargebackRatio(ratio) => write!(f, "ratio:{ratio}"),
+            Self::Never => write!(f, "never"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LockPolicy;
+    use crate::accounting::common::Amount;
+
+    #[test]
+    fn test_lock_policy_parse() {
+        for (input, policy) in [
+            ("first", LockPolicy::FirstChargeback),
+            ("after:3", LockPolicy::AfterChargebacks(3)),
+            ("ratio:0.25", LockPolicy::ChargebackRatio(0.25)),
+            ("never", LockPolicy::Never),
+        ] {
+            assert_eq!(input.parse::<LockPolicy>().unwrap(), policy);
+            assert_eq!(policy.to_string(), input);
+        }
+        for invalid in [
+            "", "always", "after", "after:0", "after:x", "ratio:-1", "never:1",
+        ] {
+            assert!(invalid.parse::<LockPolicy>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_lock_policy_should_lock() {
+        let amount = |amount| Amount::new(amount);
+        let policy = LockPolicy::FirstChargeback;
+        assert!(!policy.should_lock(0, amount(0.0), amount(100.0)));
+        assert!(policy.should_lock(1, amount(1.0), amount(100.0)));
+
+        let policy = LockPolicy::AfterChargebacks(2);
+        assert!(!policy.should_lock(1, amount(100.0), amount(100.0)));
+        assert!(policy.should_lock(2, amount(2.0), amount(100.0)));
+
+        let policy = LockPolicy::ChargebackRatio(0.1);
+        assert!(!policy.should_lock(3, amount(10.0), amount(100.0)));
+        assert!(policy.should_lock(1, amount(10.01), amount(100.0)));
+
+        assert!(!LockPolicy::Never.should_lock(100, amount(100.0), amount(100.0)));
+    }
+}
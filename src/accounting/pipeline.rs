@@ -0,0 +1,375 @@
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use csv::ByteRecord;
+
+use super::transaction::{ReadTransactionError, RecordParser, Transaction, TransactionReader};
+
+/// A batch of records or transactions with its position in the input.
+type Batch<T> = (u64, Vec<T>);
+
+type RecordResult = Result<ByteRecord, csv::Error>;
+
+type TransactionResult = Result<Transaction, ReadTransactionError>;
+
+/// A transaction with the line of its record.
+type LineResult = (u64, TransactionResult);
+
+/// [PipelineConfig] configures the threads and buffering of a
+/// [TransactionPipeline].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PipelineConfig {
+    /// Number of threads parsing records into transactions.
+    pub parsers: usize,
+
+    /// Number of records sent between threads at once.
+    pub batch_size: usize,
+
+    /// Maximum number of batches read ahead of the consumer. The reader
+    /// blocks once it is that far ahead.
+    pub capacity: usize,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            parsers: 2,
+            batch_size: 1024,
+            capacity: 64,
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn with_parsers(mut self, parsers: usize) -> Self {
+        self.parsers = parsers.max(1);
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+}
+
+/// [TransactionPipeline] reads transactions like a [TransactionReader] but
+/// splits the work over threads so that reading and parsing the input runs
+/// concurrently with handling the transactions:
+///
+/// - a reader thread splits the CSV input into batches of records,
+/// - parser threads parse the batches into transactions and hand the
+///   emptied record batches back to the reader to be refilled,
+/// - the iterator delivers the transactions in input order.
+///
+/// At most [PipelineConfig::capacity] batches are in flight, the reader
+/// waits until the consumer took the oldest batch before it reads another.
+/// Dropping the pipeline stops the threads. A panic of any of the threads
+/// stops the others and is resumed by the iterator.
+///
+/// Unlike a [TransactionReader] it stops at the first IO error.
+pub struct TransactionPipeline {
+    /// Parsed batches, None if a parser panicked.
+    results: Receiver<Option<Batch<LineResult>>>,
+
+    /// Returns a permit to read another batch to the reader.
+    permits: SyncSender<()>,
+
+    /// Batches parsed ahead of the next batch.
+    pending: BTreeMap<u64, Vec<LineResult>>,
+    next_batch: u64,
+    current: std::vec::IntoIter<LineResult>,
+    line: u64,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl TransactionPipeline {
+    pub fn new<R: Read + Send + 'static>(rdr: R, config: PipelineConfig) -> Self {
+        Self::spawn(rdr, config, RecordParser::parse)
+    }
+
+    /// Returns the line of the last transaction read, 0 before the first.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// Starts the threads parsing the records with `parse`.
+    fn spawn<R, F>(rdr: R, config: PipelineConfig, parse: F) -> Self
+    where
+        R: Read + Send + 'static,
+        F: Fn(&RecordParser, &ByteRecord) -> TransactionResult + Copy + Send + 'static,
+    {
+        let reader = TransactionReader::new(rdr);
+        let parser = reader.parser().clone();
+        let capacity = config.capacity.max(1);
+        let (permits, permits_rx) = mpsc::sync_channel(capacity);
+        for _ in 0..capacity {
+            permits
+                .try_send(())
+                .expect("the permit channel fits all permits");
+        }
+        let (work, work_rx) = mpsc::sync_channel(capacity);
+        let (results, results_rx) = mpsc::sync_channel(capacity);
+        let (recycled, recycled_rx) = mpsc::sync_channel(capacity);
+
+        let batch_size = config.batch_size.max(1);
+        let mut threads = vec![thread::spawn(move || {
+            read_batches(reader, batch_size, work, permits_rx, recycled_rx)
+        })];
+        let work_rx = Arc::new(Mutex::new(work_rx));
+        for _ in 0..config.parsers.max(1) {
+            let parser = parser.clone();
+            let (work_rx, results) = (work_rx.clone(), results.clone());
+            let recycled = recycled.clone();
+            threads.push(thread::spawn(move || {
+                let _guard = PanicGuard(results.clone());
+                parse_batches(|record| parse(&parser, record), work_rx, results, recycled)
+            }));
+        }
+        Self {
+            results: results_rx,
+            permits,
+            pending: BTreeMap::new(),
+            next_batch: 0,
+            current: Vec::new().into_iter(),
+            line: 0,
+            threads,
+        }
+    }
+
+    /// Returns the next batch in input order, None at the end of the input.
+    fn next_batch(&mut self) -> Option<Vec<LineResult>> {
+        while !self.pending.contains_key(&self.next_batch) {
+            match self.results.recv() {
+                Ok(Some((idx, batch))) => {
+                    self.pending.insert(idx, batch);
+                }
+                // a parser panicked or all parsers are done, a missing batch
+                // means that the reader panicked
+                Ok(None) | Err(_) => {
+                    self.join();
+                    return None;
+                }
+            }
+        }
+        let batch = self.pending.remove(&self.next_batch);
+        self.next_batch += 1;
+        // the reader is gone at the end of the input
+        _ = self.permits.try_send(());
+        batch
+    }
+
+    /// Stops and joins the threads and resumes a panic of any of them.
+    fn join(&mut self) {
+        // disconnecting the channels unblocks the reader waiting for a
+        // permit and the parsers waiting to deliver a batch
+        (self.permits, _) = mpsc::sync_channel(0);
+        (_, self.results) = mpsc::sync_channel(0);
+        self.pending.clear();
+        let mut panic = None;
+        for thread in self.threads.drain(..) {
+            if let Err(err) = thread.join() {
+                panic.get_or_insert(err);
+            }
+        }
+        if let Some(panic) = panic {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+impl Iterator for TransactionPipeline {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((line, tx)) = self.current.next() {
+                self.line = line;
+                return Some(tx);
+            }
+            self.current = self.next_batch()?.into_iter();
+        }
+    }
+}
+
+/// [PanicGuard] tells the consumer when the parser thread holding it
+/// panics, so that it does not wait for the batch the parser was holding.
+struct PanicGuard(SyncSender<Option<Batch<LineResult>>>);
+
+impl Drop for PanicGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            _ = self.0.send(None);
+        }
+    }
+}
+
+fn read_batches<R: Read>(
+    mut reader: TransactionReader<R>,
+    batch_size: usize,
+    work: SyncSender<Batch<RecordResult>>,
+    permits: Receiver<()>,
+    recycled: Receiver<Vec<RecordResult>>,
+) {
+    for idx in 0.. {
+        // refill the records of a parsed batch instead of allocating them
+        let mut batch = recycled
+            .try_recv()
+            .unwrap_or_else(|_| Vec::with_capacity(batch_size));
+        let mut len = 0;
+        let mut done = false;
+        while !done && len < batch_size {
+            if len == batch.len() {
+                batch.push(Ok(ByteRecord::new()));
+            }
+            if batch[len].is_err() {
+                batch[len] = Ok(ByteRecord::new());
+            }
+            let Ok(record) = &mut batch[len] else {
+                unreachable!("the slot holds a record");
+            };
+            match reader.read_byte_record(record) {
+                Ok(true) => len += 1,
+                Ok(false) => done = true,
+                Err(err) => {
+                    done = err.is_io_error();
+                    batch[len] = Err(err);
+                    len += 1;
+                }
+            }
+        }
+        batch.truncate(len);
+        // the pipeline was dropped if either channel is disconnected
+        if !batch.is_empty() && (permits.recv().is_err() || work.send((idx, batch)).is_err()) {
+            return;
+        }
+        if done {
+            return;
+        }
+    }
+}
+
+fn line_of(position: Option<&csv::Position>) -> u64 {
+    position.map_or(0, |position| position.line())
+}
+
+fn parse_batches(
+    parse: impl Fn(&ByteRecord) -> TransactionResult,
+    work: Arc<Mutex<Receiver<Batch<RecordResult>>>>,
+    results: SyncSender<Option<Batch<LineResult>>>,
+    recycled: SyncSender<Vec<RecordResult>>,
+) {
+    loop {
+        let Ok((idx, mut records)) = work.lock().expect("no panics while locked").recv() else {
+            return;
+        };
+        let transactions = records
+            .iter_mut()
+            .map(|record| match record {
+                Ok(record) => (line_of(record.position()), parse(record)),
+                Err(_) => match std::mem::replace(record, Ok(ByteRecord::new())) {
+                    Err(err) => (line_of(err.position()), Err(ReadTransactionError::Csv(err))),
+                    Ok(_) => unreachable!("the record is an error"),
+                },
+            })
+            .collect();
+        // the reader allocates a new batch if it has enough spare ones
+        _ = recycled.try_send(records);
+        if results.send(Some((idx, transactions))).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Cursor,
+        panic::{self, AssertUnwindSafe},
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    use super::{PipelineConfig, TransactionPipeline};
+    use crate::accounting::transaction::{RecordParser, TransactionReader};
+
+    #[test]
+    fn test_transaction_pipeline() {
+        let mut input = String::from("type, client, tx, amount\n");
+        for tx in 1..=1000 {
+            match tx % 7 {
+                0 => input.push_str(&format!("dispute, {}, {}\n", tx % 5, tx - 3)),
+                1 => input.push_str(&format!("withdrawal, {}, {tx}, 0.5\n", tx % 5)),
+                2 => input.push_str("invalid, row\n"),
+                _ => input.push_str(&format!("deposit, {}, {tx}, {tx}.25\n", tx % 5)),
+            }
+        }
+        let debug = |tx| format!("{tx:?}");
+        let expected: Vec<_> = TransactionReader::new(input.as_bytes())
+            .map(debug)
+            .collect();
+
+        for (parsers, batch_size, capacity) in [(1, 1, 1), (3, 7, 2), (4, 1000, 64)] {
+            let config = PipelineConfig::default()
+                .with_parsers(parsers)
+                .with_batch_size(batch_size)
+                .with_capacity(capacity);
+            let read: Vec<_> = TransactionPipeline::new(Cursor::new(input.clone()), config)
+                .map(debug)
+                .collect();
+            assert_eq!(read, expected, "{config:?}");
+        }
+
+        // dropping the pipeline early stops the threads
+        let config = PipelineConfig::default()
+            .with_batch_size(1)
+            .with_capacity(1);
+        let mut pipeline = TransactionPipeline::new(Cursor::new(input), config);
+        assert!(pipeline.next().unwrap().is_ok());
+        drop(pipeline);
+    }
+
+    #[test]
+    fn test_transaction_pipeline_parser_panic() {
+        let mut input = String::from("type, client, tx, amount\n");
+        for tx in 1..=1000 {
+            input.push_str(&format!("deposit, 1, {tx}, 1.0\n"));
+        }
+
+        // the parser holding the batch of tx 500 panics while the others
+        // fill up the capacity with later batches
+        let (done, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let config = PipelineConfig::default()
+                .with_parsers(3)
+                .with_batch_size(10)
+                .with_capacity(4);
+            let pipeline =
+                TransactionPipeline::spawn(Cursor::new(input), config, |parser, record| {
+                    if record.get(2) == Some(b" 500") {
+                        panic!("parser panic");
+                    }
+                    RecordParser::parse(parser, record)
+                });
+            let read = panic::catch_unwind(AssertUnwindSafe(|| pipeline.count()));
+            done.send(read.map_err(|err| err.downcast_ref::<&str>().copied()))
+                .unwrap();
+        });
+        let read = done_rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the pipeline stops");
+        assert_eq!(read, Err(Some("parser panic")));
+    }
+}
@@ -0,0 +1,377 @@
+use std::{
+    io::{Read, Write},
+    iter,
+};
+
+use serde::Serialize;
+
+use super::{
+    atm::{
+        AccountStatus, Atm, ClientBalanceSnapshot, ClosedAccountRow, CurrencyBalanceRow,
+        HandledTransactionError,
+    },
+    common::{ClientID, TransactionID},
+    pipeline::{PipelineConfig, TransactionPipeline},
+    registry::AccountRegistry,
+    transaction::{ReadTransactionError, Transaction, TransactionReader},
+};
+
+/// [RejectRow] is the row written to the rejects sink of a [Processor] for
+/// every input row that did not apply cleanly.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RejectRow {
+    /// Line of the input row, None if the row could not be located, e.g.
+    /// with a CSV error without a position.
+    pub line: Option<u64>,
+
+    #[serde(rename = "client")]
+    pub client_id: Option<ClientID>,
+
+    #[serde(rename = "tx")]
+    pub transaction_id: Option<TransactionID>,
+
+    /// Why the row was rejected, e.g. `InsufficientAvailableFunds`.
+    pub reason: String,
+}
+
+/// [RunReport] summarizes a [Processor] run.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RunReport {
+    /// Input rows read as transactions.
+    pub transactions: u64,
+
+    /// Transactions applied to an account.
+    pub handled: u64,
+
+    /// Transactions ignored without changing any account.
+    pub ignored: u64,
+
+    /// Transactions applied that left an account with an invalid balance.
+    pub invalid_balances: u64,
+
+    /// Input rows that could not be read as transactions.
+    pub invalid_rows: u64,
+
+    /// Accounts written to the output, without the closed ones if they are
+    /// written to a closed accounts sink.
+    pub accounts: usize,
+
+    /// Closed accounts.
+    pub closed_accounts: usize,
+}
+
+/// [Processor] reads transactions from any input, handles them with an [Atm]
+/// and writes the balances of the accounts as CSV to any output, the same way
+/// the binary does. Closed accounts are optionally written as
+/// [ClosedAccountRow]s to a closed accounts sink instead.
+///
+/// Unreadable rows and rejected transactions are skipped, optionally writing
+/// a [RejectRow] for each of them to a rejects sink. Errors reading the input
+/// or writing the output end the run.
+///
+/// Only the default command of the binary is covered, `balance-at` and
+/// `fee-report` use the [Atm] directly.
+pub struct Processor<'a> {
+    atm: Atm,
+    sorted: bool,
+    rejects: Option<csv::Writer<Box<dyn Write + 'a>>>,
+    closed_accounts: Option<Box<dyn Write + 'a>>,
+}
+
+impl<'a> Processor<'a> {
+    pub fn new(atm: Atm) -> Self {
+        Self {
+            atm,
+            sorted: false,
+            rejects: None,
+            closed_accounts: None,
+        }
+    }
+
+    /// Enables strict mode, see [Atm::with_account_registry].
+    pub fn with_account_registry(mut self, account_registry: AccountRegistry) -> Self {
+        self.atm = self.atm.with_account_registry(account_registry);
+        self
+    }
+
+    /// Writes the accounts ordered by client and currency instead of in
+    /// arbitrary order.
+    pub fn with_sorted_output(mut self, sorted: bool) -> Self {
+        self.sorted = sorted;
+        self
+    }
+
+    /// Writes a [RejectRow] to the sink for every rejected input row.
+    pub fn with_rejects(mut self, rejects: impl Write + 'a) -> Self {
+        let rejects: Box<dyn Write + 'a> = Box::new(rejects);
+        self.rejects = Some(csv::Writer::from_writer(rejects));
+        self
+    }
+
+    /// Writes a [ClosedAccountRow] to the sink for every closed account
+    /// instead of its balance to the output.
+    pub fn with_closed_accounts(mut self, closed_accounts: impl Write + 'a) -> Self {
+        self.closed_accounts = Some(Box::new(closed_accounts));
+        self
+    }
+
+    pub fn atm(&self) -> &Atm {
+        &self.atm
+    }
+
+    pub fn into_atm(self) -> Atm {
+        self.atm
+    }
+
+    /// Handles the transactions of the input read on the current thread and
+    /// writes the accounts to the output.
+    pub fn run(&mut self, input: impl Read, output: impl Write) -> anyhow::Result<RunReport> {
+        let mut reader = TransactionReader::new(input);
+        let transactions = iter::from_fn(|| Some((reader.read_transaction()?, reader.line())));
+        self.process(transactions, output)
+    }
+
+    /// Same as [Processor::run] but reads the input with a
+    /// [TransactionPipeline].
+    pub fn run_pipelined<R: Read + Send + 'static>(
+        &mut self,
+        input: R,
+        config: PipelineConfig,
+        output: impl Write,
+    ) -> anyhow::Result<RunReport> {
+        let mut pipeline = TransactionPipeline::new(input, config);
+        let transactions = iter::from_fn(|| Some((pipeline.next()?, pipeline.line())));
+        self.process(transactions, output)
+    }
+
+    fn process(
+        &mut self,
+        transactions: impl Iterator<Item = (Result<Transaction, ReadTransactionError>, u64)>,
+        output: impl Write,
+    ) -> anyhow::Result<RunReport> {
+        let mut report = RunReport::default();
+        for (tx, line) in transactions {
+            let reject = match tx {
+                Ok(tx) => {
+                    report.transactions += 1;
+                    let (client_id, transaction_id) = (tx.client_id, tx.transaction_id);
+                    let reason = match self.atm.handle_transaction(tx) {
+                        Ok(()) => {
+                            report.handled += 1;
+                            continue;
+                        }
+                        Err(HandledTransactionError::IgnoredTransactionReason(_, ignored)) => {
+                            report.ignored += 1;
+                            format!("{ignored:?}")
+                        }
+                        Err(HandledTransactionError::InvalidClientBalance(_, invalid)) => {
+                            report.invalid_balances += 1;
+                            format!("{invalid:?}")
+                        }
+                    };
+                    RejectRow {
+                        line: Some(line),
+                        client_id: Some(client_id),
+                        transaction_id: Some(transaction_id),
+                        reason,
+                    }
+                }
+                Err(ReadTransactionError::Csv(err)) if err.is_io_error() => return Err(err.into()),
+                Err(err) => {
+                    report.invalid_rows += 1;
+                    let (line, reason) = match err {
+                        ReadTransactionError::InvalidRecord(line) => {
+                            (Some(line), "InvalidRecord".to_string())
+                        }
+                        ReadTransactionError::Csv(err) => {
+                            let line = err.position().map(|position| position.line());
+                            (line, err.to_string())
+                        }
+                    };
+                    RejectRow {
+                        line,
+                        client_id: None,
+                        transaction_id: None,
+                        reason,
+                    }
+                }
+            };
+            if let Some(rejects) = &mut self.rejects {
+                rejects.serialize(reject)?;
+            }
+        }
+        if let Some(rejects) = &mut self.rejects {
+            rejects.flush()?;
+        }
+        self.write_accounts(output, &mut report)?;
+        Ok(report)
+    }
+
+    fn write_accounts(&mut self, output: impl Write, report: &mut RunReport) -> anyhow::Result<()> {
+        // closed accounts are reported to their own sink if there is one
+        let has_sink = self.closed_accounts.is_some();
+        let mut accounts: Vec<_> = self
+            .atm
+            .accounts()
+            .filter(|snapshot| !has_sink || snapshot.status() != AccountStatus::Closed)
+            .collect();
+        let mut closed_accounts: Vec<_> = self.atm.closed_accounts().collect();
+        if self.sorted {
+            accounts.sort_by_key(|snapshot| (snapshot.client_id().0, snapshot.currency()));
+            closed_accounts.sort_by_key(|row| (row.client_id.0, row.currency));
+        }
+        report.accounts = accounts.len();
+        report.closed_accounts = closed_accounts.len();
+
+        if self.atm.is_multi_currency() {
+            let rows = accounts.into_iter().map(CurrencyBalanceRow::from);
+            write_rows(output, &CurrencyBalanceRow::HEADER, rows)?;
+        } else {
+            write_rows(output, &ClientBalanceSnapshot::HEADER, accounts)?;
+        }
+        if let Some(sink) = &mut self.closed_accounts {
+            write_rows(sink, &ClosedAccountRow::HEADER, closed_accounts)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the header followed by the rows, so that the output is valid CSV
+/// even without any rows.
+fn write_rows(
+    output: impl Write,
+    header: &[&str],
+    rows: impl IntoIterator<Item = impl Serialize>,
+) -> anyhow::Result<()> {
+    let mut csv_writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(output);
+    csv_writer.write_record(header)?;
+    for row in rows {
+        csv_writer.serialize(row)?
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Processor, RunReport};
+    use crate::accounting::{
+        atm::Atm, common::ClientID, pipeline::PipelineConfig, registry::AccountRegistry,
+    };
+
+    #[test]
+    fn test_processor() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 3, 1, 10.0\n\
+                     deposit, 1, 2, 5.0\n\
+                     withdrawal, 1, 3, 6.0\n\
+                     deposit, 2, 4, 1.0\n\
+                     unknown, 1, 5, 1.0\n\
+                     deposit, 3, 1, 1.0\n\
+                     close, 3, 6\n";
+        let registry = AccountRegistry::default()
+            .with_client(ClientID(1))
+            .with_client(ClientID(3));
+
+        let mut output = Vec::new();
+        let mut rejects = Vec::new();
+        let mut closed_accounts = Vec::new();
+        let mut processor = Processor::new(Atm::default())
+            .with_account_registry(registry)
+            .with_sorted_output(true)
+            .with_rejects(&mut rejects)
+            .with_closed_accounts(&mut closed_accounts);
+        let report = processor.run(input.as_bytes(), &mut output).unwrap();
+        drop(processor);
+        assert_eq!(
+            report,
+            RunReport {
+                transactions: 6,
+                handled: 3,
+                ignored: 3,
+                invalid_balances: 0,
+                invalid_rows: 1,
+                accounts: 1,
+                closed_accounts: 1,
+            }
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n\
+             1,5.0,0.0,5.0,false\n"
+        );
+        assert_eq!(
+            String::from_utf8(closed_accounts).unwrap(),
+            "client,currency,tx,paid_out\n\
+             3,,6,10.0\n"
+        );
+        assert_eq!(
+            String::from_utf8(rejects).unwrap(),
+            "line,client,tx,reason\n\
+             4,1,3,InsufficientAvailableFunds\n\
+             5,2,4,UnknownClient\n\
+             6,,,InvalidRecord\n\
+             7,3,1,DuplicateTransactionIDInsertion\n"
+        );
+
+        // closed accounts stay in the output without a closed accounts sink
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 10.0\n\
+                     close, 1, 2\n";
+        let mut output = Vec::new();
+        let report = Processor::new(Atm::default())
+            .run(input.as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!((report.accounts, report.closed_accounts), (1, 1));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n\
+             1,0.0,0.0,0.0,true\n"
+        );
+
+        // the header is written even if every account is closed
+        let mut output = Vec::new();
+        Processor::new(Atm::default())
+            .with_closed_accounts(std::io::sink())
+            .run(input.as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n"
+        );
+
+        // the pipeline produces the same output and rejects
+        let (mut sequential, mut sequential_rejects) = (Vec::new(), Vec::new());
+        let (mut pipelined, mut pipelined_rejects) = (Vec::new(), Vec::new());
+        let many: String = (1..=200)
+            .map(|tx| match tx % 10 {
+                0 => format!("withdrawal, {}, {tx}, 100.0\n", tx % 7),
+                5 => "invalid, row\n".to_string(),
+                _ => format!("deposit, {}, {tx}, 1.5\n", tx % 7),
+            })
+            .collect();
+        let input = format!("type, client, tx, amount\n{many}");
+        let sequential_report = Processor::new(Atm::default())
+            .with_sorted_output(true)
+            .with_rejects(&mut sequential_rejects)
+            .run(input.as_bytes(), &mut sequential)
+            .unwrap();
+        let pipelined_report = Processor::new(Atm::default())
+            .with_sorted_output(true)
+            .with_rejects(&mut pipelined_rejects)
+            .run_pipelined(
+                std::io::Cursor::new(input),
+                PipelineConfig::default().with_batch_size(16),
+                &mut pipelined,
+            )
+            .unwrap();
+        assert_eq!(sequential_report, pipelined_report);
+        assert_eq!(sequential, pipelined);
+        assert_eq!(sequential_rejects, pipelined_rejects);
+        let rejects = String::from_utf8(pipelined_rejects).unwrap();
+        assert_eq!(rejects.lines().count(), 1 + 40);
+        assert!(rejects.contains("\n11,3,10,InsufficientAvailableFunds\n"));
+    }
+}
@@ -0,0 +1,390 @@
+//! A deliberately simple reference model of the [Atm] semantics used to
+//! differentially test it. The model only covers the default configuration
+//! (no limits, fees, exchange rates, interest or account registry and the
+//! first chargeback locks the account) and currency-less transactions.
+//!
+//! It keeps every accepted Deposit and Withdrawal with its held, charged
+//! back and reversed parts in integer units and derives the balances from
+//! them instead of updating them incrementally.
+
+use std::collections::HashMap;
+
+use super::{
+    atm::{HandledTransactionResult, IgnoredTransactionReason},
+    common::{Amount, ClientID, TransactionID},
+    transaction::{Transaction, TransactionType},
+};
+
+/// Converts the amount into integer units of the smallest decimal place.
+fn units(amount: Amount) -> i64 {
+    (f64::from(amount) * 10f64.powi(Amount::DEFAULT_DECIMALS as i32)).round() as i64
+}
+
+/// [ReferenceState] is the state of a transaction derived from its parts.
+#[derive(Debug, PartialEq)]
+enum ReferenceState {
+    Resolved,
+    Disputed,
+    Chargeback,
+    Reversed,
+}
+
+/// [ReferenceTransaction] is an accepted Deposit or Withdrawal.
+#[derive(Debug)]
+struct ReferenceTransaction {
+    amount: i64,
+    is_deposit: bool,
+    held: i64,
+    charged_back: i64,
+    reversed: bool,
+}
+
+impl ReferenceTransaction {
+    /// Returns the sign of the balance effect.
+    fn sign(&self) -> i64 {
+        if self.is_deposit {
+            1
+        } else {
+            -1
+        }
+    }
+
+    fn undisputed(&self) -> i64 {
+        self.amount - self.held - self.charged_back
+    }
+
+    fn state(&self) -> ReferenceState {
+        if self.reversed {
+            ReferenceState::Reversed
+        } else if self.held > 0 {
+            ReferenceState::Disputed
+        } else if self.charged_back == self.amount {
+            ReferenceState::Chargeback
+        } else {
+            ReferenceState::Resolved
+        }
+    }
+}
+
+/// [ReferenceAccount] is the currency-less account of a client.
+#[derive(Debug, Default)]
+pub struct ReferenceAccount {
+    transactions: HashMap<TransactionID, ReferenceTransaction>,
+    locked: bool,
+    closed: bool,
+}
+
+impl ReferenceAccount {
+    pub fn total(&self) -> i64 {
+        self.transactions
+            .values()
+            .filter(|tx| !tx.reversed)
+            .map(|tx| tx.sign() * (tx.amount - tx.charged_back))
+            .sum()
+    }
+
+    pub fn held(&self) -> i64 {
+        self.transactions
+            .values()
+            .map(|tx| tx.sign() * tx.held)
+            .sum()
+    }
+
+    pub fn available(&self) -> i64 {
+        self.total() - self.held()
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+
+    fn handle(
+        &mut self,
+        transaction_id: TransactionID,
+        transaction_type: TransactionType,
+    ) -> Result<(), IgnoredTransactionReason> {
+        use TransactionType::*;
+        if self.closed {
+            return Err(IgnoredTransactionReason::ClosedAccount);
+        }
+        if self.locked {
+            return Err(IgnoredTransactionReason::LockedAccount);
+        }
+        match transaction_type {
+            Deposit(amount) => self.insert(transaction_id, units(amount), true),
+            Withdrawal(amount) => self.insert(transaction_id, units(amount), false),
+            Dispute => self.dispute(transaction_id, None),
+            PartialDispute(part) => self.dispute(transaction_id, Some(units(part))),
+            Resolve => self.resolve(transaction_id, None),
+            PartialResolve(part) => self.resolve(transaction_id, Some(units(part))),
+            Chargeback => self.chargeback(transaction_id, None),
+            PartialChargeback(part) => self.chargeback(transaction_id, Some(units(part))),
+            Reversal => self.reverse(transaction_id),
+            Close => self.close(transaction_id),
+        }
+    }
+
+    fn insert(
+        &mut self,
+        transaction_id: TransactionID,
+        amount: i64,
+        is_deposit: bool,
+    ) -> Result<(), IgnoredTransactionReason> {
+        use IgnoredTransactionReason::*;
+        if transaction_id.is_internal() {
+            return Err(ReservedTransactionID);
+        }
+        if amount < 0 {
+            return Err(NegativeAmount);
+        }
+        if amount == 0 {
+            return Err(ZeroAmount);
+        }
+        if self.transactions.contains_key(&transaction_id) {
+            return Err(DuplicateTransactionIDInsertion);
+        }
+        if !is_deposit && self.available() < amount {
+            return Err(InsufficientAvailableFunds);
+        }
+        let tx = ReferenceTransaction {
+            amount,
+            is_deposit,
+            held: 0,
+            charged_back: 0,
+            reversed: false,
+        };
+        self.transactions.insert(transaction_id, tx);
+        Ok(())
+    }
+
+    fn get_mut(
+        &mut self,
+        transaction_id: TransactionID,
+    ) -> Result<&mut ReferenceTransaction, IgnoredTransactionReason> {
+        self.transactions
+            .get_mut(&transaction_id)
+            .ok_or(IgnoredTransactionReason::MissingTransactionID)
+    }
+
+    /// Returns the part to act on, all of the available part if missing.
+    fn part(part: Option<i64>, available: i64) -> Result<i64, IgnoredTransactionReason> {
+        use IgnoredTransactionReason::*;
+        match part {
+            Some(part) if part < 0 => Err(NegativeAmount),
+            Some(0) => Err(ZeroAmount),
+            Some(part) if part > available => Err(DisputedAmountExceeded),
+            Some(part) => Ok(part),
+            None => Ok(available),
+        }
+    }
+
+    fn dispute(
+        &mut self,
+        transaction_id: TransactionID,
+        part: Option<i64>,
+    ) -> Result<(), IgnoredTransactionReason> {
+        use IgnoredTransactionReason::*;
+        let tx = self.get_mut(transaction_id)?;
+        match tx.state() {
+            ReferenceState::Chargeback | ReferenceState::Reversed => {
+                return Err(InvalidTransactionStateTransition)
+            }
+            ReferenceState::Disputed if part.is_none() => return Err(NoTransactionStateChange),
+            ReferenceState::Disputed | ReferenceState::Resolved => {}
+        }
+        tx.held += Self::part(part, tx.undisputed())?;
+        Ok(())
+    }
+
+    fn resolve(
+        &mut self,
+        transaction_id: TransactionID,
+        part: Option<i64>,
+    ) -> Result<(), IgnoredTransactionReason> {
+        use IgnoredTransactionReason::*;
+        let tx = self.get_mut(transaction_id)?;
+        match tx.state() {
+            ReferenceState::Disputed => {}
+            ReferenceState::Resolved => return Err(NoTransactionStateChange),
+            _ => return Err(InvalidTransactionStateTransition),
+        }
+        tx.held -= Self::part(part, tx.held)?;
+        Ok(())
+    }
+
+    fn chargeback(
+        &mut self,
+        transaction_id: TransactionID,
+        part: Option<i64>,
+    ) -> Result<(), IgnoredTransactionReason> {
+        let tx = self.get_mut(transaction_id)?;
+        if tx.state() != ReferenceState::Disputed {
+            return Err(IgnoredTransactionReason::InvalidTransactionStateTransition);
+        }
+        let part = Self::part(part, tx.held)?;
+        tx.held -= part;
+        tx.charged_back += part;
+        self.locked = true;
+        Ok(())
+    }
+
+    fn reverse(&mut self, transaction_id: TransactionID) -> Result<(), IgnoredTransactionReason> {
+        use IgnoredTransactionReason::*;
+        if transaction_id.is_internal() {
+            return Err(ReservedTransactionID);
+        }
+        let available = self.available();
+        let tx = self.get_mut(transaction_id)?;
+        match tx.state() {
+            ReferenceState::Resolved if tx.charged_back == 0 => {
+                if tx.is_deposit && available < tx.amount {
+                    return Err(InsufficientAvailableFunds);
+                }
+                tx.reversed = true
+            }
+            ReferenceState::Reversed => return Err(NoTransactionStateChange),
+            _ => return Err(InvalidTransactionStateTransition),
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, transaction_id: TransactionID) -> Result<(), IgnoredTransactionReason> {
+        use IgnoredTransactionReason::*;
+        if self.transactions.values().any(|tx| tx.held != 0) {
+            return Err(OpenDispute);
+        }
+        let available = self.available();
+        if available < 0 {
+            return Err(NegativeAvailableFunds);
+        }
+        if transaction_id.is_internal() {
+            return Err(ReservedTransactionID);
+        }
+        if self.transactions.contains_key(&transaction_id) {
+            return Err(DuplicateTransactionIDInsertion);
+        }
+        if available > 0 {
+            self.insert(transaction_id, available, false)?;
+        }
+        self.closed = true;
+        Ok(())
+    }
+}
+
+/// [ReferenceAtm] holds the [ReferenceAccount] of every client. Like the
+/// [Atm] it opens an account for every client it sees a transaction of,
+/// except for a Close.
+#[derive(Debug, Default)]
+pub struct ReferenceAtm {
+    accounts: HashMap<ClientID, ReferenceAccount>,
+}
+
+impl ReferenceAtm {
+    pub fn handle_transaction(&mut self, tx: Transaction) -> HandledTransactionResult {
+        let is_close = matches!(tx.transaction_type, TransactionType::Close);
+        if is_close && !self.accounts.contains_key(&tx.client_id) {
+            let ignored = IgnoredTransactionReason::MissingAccount;
+            return Err((tx.transaction_id, ignored).into());
+        }
+        let account = self.accounts.entry(tx.client_id).or_default();
+        account
+            .handle(tx.transaction_id, tx.transaction_type)
+            .map_err(|ignored| (tx.transaction_id, ignored).into())
+    }
+
+    pub fn account(&self, client_id: ClientID) -> Option<&ReferenceAccount> {
+        self.accounts.get(&client_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::{units, ReferenceAtm};
+    use crate::accounting::{
+        atm::{AccountStatus, Atm},
+        common::{Amount, ClientID, TransactionID},
+        transaction::{Transaction, TransactionType},
+    };
+
+    fn amount() -> impl Strategy<Value = Amount> {
+        prop_oneof![
+            20 => 1i64..=1_000_000,
+            1 => Just(0i64),
+            1 => -10_000i64..0,
+        ]
+        .prop_map(|units| Amount::new(units as f64 / 10_000.0))
+    }
+
+    fn transaction_type() -> impl Strategy<Value = TransactionType> {
+        use TransactionType::*;
+        prop_oneof![
+            8 => amount().prop_map(Deposit),
+            6 => amount().prop_map(Withdrawal),
+            3 => Just(Dispute),
+            2 => Just(Resolve),
+            1 => Just(Chargeback),
+            1 => Just(Reversal),
+            2 => amount().prop_map(PartialDispute),
+            1 => amount().prop_map(PartialResolve),
+            1 => amount().prop_map(PartialChargeback),
+            1 => Just(Close),
+        ]
+    }
+
+    fn transaction() -> impl Strategy<Value = (u16, u32, TransactionType)> {
+        let transaction_id = prop_oneof![
+            30 => 1u32..=24,
+            1 => Just(TransactionID::FIRST_INTERNAL.0),
+        ];
+        (1u16..=4, transaction_id, transaction_type())
+    }
+
+    proptest! {
+        #[test]
+        fn test_atm_matches_reference_model(
+            transactions in prop::collection::vec(transaction(), 0..200),
+        ) {
+            let mut atm = Atm::default();
+            let mut reference = ReferenceAtm::default();
+            for (client_id, transaction_id, transaction_type) in transactions {
+                let tx = || Transaction {
+                    client_id: ClientID(client_id),
+                    transaction_id: TransactionID(transaction_id),
+                    transaction_type,
+                    currency: None,
+                };
+                prop_assert_eq!(
+                    atm.handle_transaction(tx()),
+                    reference.handle_transaction(tx()),
+                    "{:?}",
+                    tx()
+                );
+            }
+
+            prop_assert_eq!(atm.accounts().count(), reference.len());
+            for snapshot in atm.accounts() {
+                let account = reference.account(snapshot.client_id()).unwrap();
+                prop_assert_eq!(units(snapshot.available()), account.available());
+                prop_assert_eq!(units(snapshot.held()), account.held());
+                prop_assert_eq!(units(snapshot.total()), account.total());
+                let status = match (account.closed(), account.locked()) {
+                    (true, _) => AccountStatus::Closed,
+                    (false, true) => AccountStatus::FullyFrozen,
+                    (false, false) => AccountStatus::Active,
+                };
+                prop_assert_eq!(snapshot.status(), status);
+                prop_assert_eq!(snapshot.locked(), status.is_locked());
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::{collections::HashSet, io::Read, path::Path};
+
+use csv::Trim;
+use serde::Deserialize;
+
+use super::common::ClientID;
+
+/// [AccountRegistryRecord] is used as a deserialization helper struct ONLY.
+#[derive(Debug, Deserialize)]
+struct AccountRegistryRecord {
+    #[serde(rename = "client")]
+    client_id: ClientID,
+}
+
+/// [AccountRegistry] holds the clients that have opened an account. With a
+/// registry the [Atm](super::atm::Atm) runs in strict mode and ignores the
+/// transactions of unregistered clients.
+///
+/// It can be loaded from a CSV file with a `client` column:
+///
+/// ```csv
+/// client
+/// 1
+/// 2
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AccountRegistry {
+    clients: HashSet<ClientID>,
+}
+
+impl AccountRegistry {
+    pub fn with_client(mut self, client_id: ClientID) -> Self {
+        self.register(client_id);
+        self
+    }
+
+    pub fn from_reader(rdr: impl Read) -> anyhow::Result<Self> {
+        let mut rdr = csv::ReaderBuilder::new().trim(Trim::All).from_reader(rdr);
+        let mut registry = Self::default();
+        for record in rdr.deserialize::<AccountRegistryRecord>() {
+            registry.register(record?.client_id);
+        }
+        Ok(registry)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_reader(std::fs::File::open(path)?)
+    }
+
+    /// Registers the client. Returns false if it was already registered.
+    pub fn register(&mut self, client_id: ClientID) -> bool {
+        self.clients.insert(client_id)
+    }
+
+    pub fn contains(&self, client_id: ClientID) -> bool {
+        self.clients.contains(&client_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AccountRegistry;
+    use crate::accounting::common::ClientID;
+
+    #[test]
+    fn test_account_registry_from_reader() {
+        let mut registry = AccountRegistry::from_reader("client\n 1\n2\n2\n".as_bytes()).unwrap();
+        assert_eq!(registry.len(), 2);
+        assert!(registry.contains(ClientID(1)));
+        assert!(!registry.contains(ClientID(3)));
+        assert!(registry.register(ClientID(3)));
+        assert!(!registry.register(ClientID(3)));
+        assert_eq!(
+            registry,
+            AccountRegistry::default()
+                .with_client(ClientID(1))
+                .with_client(ClientID(2))
+                .with_client(ClientID(3))
+        );
+
+        for invalid in ["client\nx\n", "client\n-1\n", "client\n70000\n"] {
+            assert!(
+                AccountRegistry::from_reader(invalid.as_bytes()).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::{collections::BTreeMap, mem::size_of};
+
+use super::common::{Amount, TransactionID};
+
+/// [SortedRun] keeps transactions sorted by [TransactionID] in parallel
+/// vectors so that an entry takes no more than its fields. Transactions
+/// inserted out of order, below the last ID of the vectors, go to a
+/// [BTreeMap] instead.
+#[derive(Debug)]
+struct SortedRun<T> {
+    ids: Vec<TransactionID>,
+    amounts: Vec<Amount>,
+    tags: Vec<T>,
+    out_of_order: BTreeMap<TransactionID, (Amount, T)>,
+}
+
+impl<T> Default for SortedRun<T> {
+    fn default() -> Self {
+        Self {
+            ids: Vec::new(),
+            amounts: Vec::new(),
+            tags: Vec::new(),
+            out_of_order: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Copy> SortedRun<T> {
+    /// Returns the index of the transaction in the vectors, None if it is
+    /// not stored in them.
+    fn position(&self, transaction_id: TransactionID) -> Option<usize> {
+        match self.ids.last() {
+            Some(last) if *last >= transaction_id => self.ids.binary_search(&transaction_id).ok(),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, transaction_id: TransactionID, amount: Amount, tag: T) -> bool {
+        // appending in ascending order is the common case
+        if self.ids.last().is_none_or(|last| *last < transaction_id) {
+            self.ids.push(transaction_id);
+            self.amounts.push(amount);
+            self.tags.push(tag);
+            return true;
+        }
+        if self.position(transaction_id).is_some()
+            || self.out_of_order.contains_key(&transaction_id)
+        {
+            return false;
+        }
+        self.out_of_order.insert(transaction_id, (amount, tag));
+        true
+    }
+
+    fn get(&self, transaction_id: TransactionID) -> Option<(Amount, T)> {
+        match self.position(transaction_id) {
+            Some(idx) => Some((self.amounts[idx], self.tags[idx])),
+            None => self.out_of_order.get(&transaction_id).copied(),
+        }
+    }
+
+    fn set_tag(&mut self, transaction_id: TransactionID, tag: T) -> bool {
+        if let Some(idx) = self.position(transaction_id) {
+            self.tags[idx] = tag;
+            return true;
+        }
+        match self.out_of_order.get_mut(&transaction_id) {
+            Some((_, stored)) => {
+                *stored = tag;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (TransactionID, Amount, T)> + '_ {
+        let mut sorted = self
+            .ids
+            .iter()
+            .zip(&self.amounts)
+            .zip(&self.tags)
+            .map(|((id, amount), tag)| (*id, *amount, *tag))
+            .peekable();
+        let mut out_of_order = self
+            .out_of_order
+            .iter()
+            .map(|(id, (amount, tag))| (*id, *amount, *tag))
+            .peekable();
+        // merges the two ascending sequences, the IDs are distinct
+        std::iter::from_fn(move || match (sorted.peek(), out_of_order.peek()) {
+            (Some(a), Some(b)) if b.0 < a.0 => out_of_order.next(),
+            (Some(_), _) => sorted.next(),
+            (None, _) => out_of_order.next(),
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.ids.len() + self.out_of_order.len()
+    }
+
+    /// Returns the bytes allocated by the vectors and an estimate for the
+    /// nodes of the map, which are not exposed.
+    fn heap_bytes(&self) -> usize {
+        self.ids.capacity() * size_of::<TransactionID>()
+            + self.amounts.capacity() * size_of::<Amount>()
+            + self.tags.capacity() * size_of::<T>()
+            + self.out_of_order.len() * size_of::<(TransactionID, (Amount, T))>()
+    }
+}
+
+/// [TransactionStore] is the compact store of the transactions of an
+/// account: an [Amount] and a small tag (e.g. the kind and state) per
+/// [TransactionID].
+///
+/// Transactions are kept in sorted vectors, one for input and one for
+/// internal [TransactionID]s which are both usually inserted in ascending
+/// order and therefore appended in O(1). Transactions inserted out of order
+/// go to a [BTreeMap] in O(log n), which costs more memory per entry.
+#[derive(Debug)]
+pub struct TransactionStore<T> {
+    external: SortedRun<T>,
+    internal: SortedRun<T>,
+}
+
+impl<T> Default for TransactionStore<T> {
+    fn default() -> Self {
+        Self {
+            external: SortedRun::default(),
+            internal: SortedRun::default(),
+        }
+    }
+}
+
+impl<T: Copy> TransactionStore<T> {
+    fn run(&self, transaction_id: TransactionID) -> &SortedRun<T> {
+        if transaction_id.is_internal() {
+            &self.internal
+        } else {
+            &self.external
+        }
+    }
+
+    fn run_mut(&mut self, transaction_id: TransactionID) -> &mut SortedRun<T> {
+        if transaction_id.is_internal() {
+            &mut self.internal
+        } else {
+            &mut self.external
+        }
+    }
+
+    /// Inserts the transaction. Returns false if the [TransactionID] is
+    /// already stored.
+    pub fn insert(&mut self, transaction_id: TransactionID, amount: Amount, tag: T) -> bool {
+        self.run_mut(transaction_id)
+            .insert(transaction_id, amount, tag)
+    }
+
+    pub fn get(&self, transaction_id: TransactionID) -> Option<(Amount, T)> {
+        self.run(transaction_id).get(transaction_id)
+    }
+
+    pub fn contains(&self, transaction_id: TransactionID) -> bool {
+        self.get(transaction_id).is_some()
+    }
+
+    /// Replaces the tag of the transaction. Returns false if the
+    /// [TransactionID] is not stored.
+    pub fn set_tag(&mut self, transaction_id: TransactionID, tag: T) -> bool {
+        self.run_mut(transaction_id).set_tag(transaction_id, tag)
+    }
+
+    /// Returns the transactions in ascending [TransactionID] order.
+    pub fn iter(&self) -> impl Iterator<Item = (TransactionID, Amount, T)> + '_ {
+        self.external.iter().chain(self.internal.iter())
+    }
+
+    pub fn len(&self) -> usize {
+        self.external.len() + self.internal.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of bytes allocated on the heap.
+    pub fn heap_bytes(&self) -> usize {
+        self.external.heap_bytes() + self.internal.heap_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    use super::TransactionStore;
+    use crate::accounting::common::{Amount, TransactionID};
+
+    #[test]
+    fn test_transaction_store() {
+        let mut store = TransactionStore::default();
+        let internal = TransactionID::FIRST_INTERNAL;
+        for id in [5, 1, 3, 9] {
+            assert!(store.insert(TransactionID(id), Amount::new(id as f64), id as u8));
+        }
+        assert!(store.insert(internal, Amount::new(0.5), 0));
+        assert!(store.insert(TransactionID(7), Amount::new(7.0), 7));
+        assert!(
+            !store.insert(TransactionID(3), Amount::new(1.0), 0),
+            "duplicate"
+        );
+        assert_eq!(store.len(), 6);
+
+        assert_eq!(store.get(TransactionID(3)), Some((Amount::new(3.0), 3)));
+        assert_eq!(store.get(internal), Some((Amount::new(0.5), 0)));
+        assert!(!store.contains(TransactionID(4)));
+        assert!(store.set_tag(TransactionID(9), 10));
+        assert!(!store.set_tag(TransactionID(10), 10));
+        assert_eq!(store.get(TransactionID(9)), Some((Amount::new(9.0), 10)));
+
+        let ids: Vec<_> = store.iter().map(|(id, _, _)| id.0).collect();
+        assert_eq!(ids, vec![1, 3, 5, 7, 9, internal.0]);
+    }
+
+    #[test]
+    fn test_transaction_store_out_of_order() {
+        const N: u32 = 200_000;
+        let mut ids: Vec<u32> = (0..N).collect();
+        ids.shuffle(&mut StdRng::seed_from_u64(7));
+
+        let start = Instant::now();
+        let mut store = TransactionStore::default();
+        for &id in &ids {
+            assert!(store.insert(TransactionID(id), Amount::new(1.0), 0u8));
+        }
+        for &id in &ids {
+            assert!(store.set_tag(TransactionID(id), 1));
+            assert!(!store.insert(TransactionID(id), Amount::new(1.0), 0));
+        }
+        // shifting the vectors on every insert would move about n²/2 entries
+        let elapsed = start.elapsed();
+        assert!(elapsed < Duration::from_secs(10), "{elapsed:?}");
+
+        assert_eq!(store.len(), N as usize);
+        assert!(store.iter().map(|(id, _, _)| id.0).eq(0..N));
+        assert!(store.iter().all(|(_, _, tag)| tag == 1));
+    }
+}
@@ -1,4 +1,7 @@
-use super::common::{Amount, ClientID, TransactionID};
+use std::io::Read;
+
+use super::common::{Amount, ClientID, Currency, TransactionID};
+use csv::{ByteRecord, Trim};
 use serde::Deserialize;
 
 /// [InputTransactionRecord](InputTransactionRecord) is used as a deserialization
@@ -16,6 +19,9 @@ struct InputTransactionRecord {
 
     #[serde(rename = "amount")]
     amount: Option<Amount>,
+
+    #[serde(rename = "currency", default)]
+    currency: Option<Currency>,
 }
 
 /// [TransactionType] represants possible transaction types.
@@ -36,6 +42,53 @@ pub enum TransactionType {
     /// Chargeback represents a Dispute confirmation meaning that there was
     /// an erronious transaction.
     Chargeback,
+
+    /// Reversal represents an operator cancelling an erroneous Deposit or
+    /// Withdrawal outright without going through a Dispute.
+    Reversal,
+
+    /// PartialDispute represents a Dispute of part of a transaction amount.
+    PartialDispute(Amount),
+
+    /// PartialResolve represents a Resolve of part of the disputed amount.
+    PartialResolve(Amount),
+
+    /// PartialChargeback represents a Chargeback of part of the disputed
+    /// amount.
+    PartialChargeback(Amount),
+
+    /// Close represents closing the account, paying out the available amount
+    /// as a final Withdrawal with the given transaction ID.
+    Close,
+}
+
+impl TransactionType {
+    /// Returns true for the types booking a new transaction under their
+    /// [TransactionID], which has to be unique per client.
+    pub fn inserts_transaction(&self) -> bool {
+        matches!(self, Self::Deposit(_) | Self::Withdrawal(_) | Self::Close)
+    }
+
+    /// Matches the type of an input record and its optional amount. Returns
+    /// None for unknown types and for Deposits and Withdrawals without an
+    /// amount.
+    fn from_record(record_type: &[u8], amount: Option<Amount>) -> Option<Self> {
+        use TransactionType::*;
+        let transaction_type = match (record_type, amount) {
+            (b"deposit", Some(amount)) => Deposit(amount),
+            (b"withdrawal", Some(amount)) => Withdrawal(amount),
+            (b"dispute", Some(amount)) => PartialDispute(amount),
+            (b"dispute", None) => Dispute,
+            (b"resolve", Some(amount)) => PartialResolve(amount),
+            (b"resolve", None) => Resolve,
+            (b"chargeback", Some(amount)) => PartialChargeback(amount),
+            (b"chargeback", None) => Chargeback,
+            (b"reversal", _) => Reversal,
+            (b"close", _) => Close,
+            _ => return None,
+        };
+        Some(transaction_type)
+    }
 }
 
 /// [Transaction] represents a transaction type for a given
@@ -52,6 +105,10 @@ pub struct Transaction {
     /// Specifies the transaction type. The transaction type defines how to handle
     /// a given transaction.
     pub transaction_type: TransactionType,
+
+    /// Optional currency of the transaction. Transactions without a currency
+    /// are booked on the currency-less account of the client.
+    pub currency: Option<Currency>,
 }
 
 impl<'de> Deserialize<'de> for Transaction {
@@ -60,28 +117,369 @@ impl<'de> Deserialize<'de> for Transaction {
         D: serde::de::Deserializer<'de>,
     {
         let tmp = InputTransactionRecord::deserialize(deserializer)?;
-        use TransactionType::*;
         let client_id = tmp.client_id;
         let transaction_id = tmp.transaction_id;
-        let transaction_type = match (tmp.record_type.as_str(), tmp.amount) {
-            ("deposit", Some(amount)) => Deposit(amount),
-            ("withdrawal", Some(amount)) => Withdrawal(amount),
-            ("dispute", _) => Dispute,
-            ("resolve", _) => Resolve,
-            ("chargeback", _) => Chargeback,
-            _ => {
-                let missing_amount = tmp.amount.is_none();
-                let err_msg = format!(
-                    "Unknown type '{}' and/or missing amount '{}'",
-                    &tmp.record_type, missing_amount
-                );
-                return Err(serde::de::Error::custom(err_msg));
-            }
+        let currency = tmp.currency;
+        let Some(transaction_type) =
+            TransactionType::from_record(tmp.record_type.as_bytes(), tmp.amount)
+        else {
+            let missing_amount = tmp.amount.is_none();
+            let err_msg = format!(
+                "Unknown type '{}' and/or missing amount '{}'",
+                &tmp.record_type, missing_amount
+            );
+            return Err(serde::de::Error::custom(err_msg));
         };
         Ok(Transaction {
             client_id,
             transaction_id,
             transaction_type,
+            currency,
+        })
+    }
+}
+
+/// Returns a CSV reader for untrusted transaction input. Fields are trimmed
+/// and rows may omit the optional trailing columns.
+pub fn csv_reader<R: Read>(rdr: R) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_reader(rdr)
+}
+
+/// [ReadTransactionError] states why a [TransactionReader] could not read a
+/// transaction.
+#[derive(Debug)]
+pub enum ReadTransactionError {
+    /// Csv represents an error reading the CSV input, e.g. invalid UTF-8 or
+    /// an IO error.
+    Csv(csv::Error),
+
+    /// InvalidRecord represents a record on the given line that is not a
+    /// valid transaction, e.g. with an unknown type or a missing or invalid
+    /// field.
+    InvalidRecord(u64),
+}
+
+/// [Columns] maps the fields of a record to the fields of an
+/// [InputTransactionRecord] the same way serde does with the header row.
+#[derive(Debug, Default, Clone)]
+struct Columns {
+    record_type: Option<usize>,
+    client_id: Option<usize>,
+    transaction_id: Option<usize>,
+    amount: Option<usize>,
+    currency: Option<usize>,
+
+    /// Records with fewer fields are missing a required field.
+    min_len: usize,
+
+    /// No record is valid, e.g. because of a missing or duplicate column.
+    invalid: bool,
+}
+
+impl Columns {
+    /// Returns the columns in the order of the [InputTransactionRecord]
+    /// fields, used when the header row can not be read.
+    fn positional() -> Self {
+        Self {
+            record_type: Some(0),
+            client_id: Some(1),
+            transaction_id: Some(2),
+            amount: Some(3),
+            currency: Some(4),
+            min_len: 4,
+            invalid: false,
+        }
+    }
+
+    fn from_headers(headers: &ByteRecord) -> Self {
+        let mut columns = Self::default();
+        for (idx, name) in headers.iter().enumerate() {
+            let Ok(name) = std::str::from_utf8(name) else {
+                return Self::positional();
+            };
+            let (column, required) = match name.trim() {
+                "type" => (&mut columns.record_type, true),
+                "client" => (&mut columns.client_id, true),
+                "tx" => (&mut columns.transaction_id, true),
+                "amount" => (&mut columns.amount, false),
+                "currency" => (&mut columns.currency, false),
+                // the fields of unknown columns are skipped but must exist
+                _ => {
+                    columns.min_len = idx + 1;
+                    continue;
+                }
+            };
+            columns.invalid |= column.replace(idx).is_some();
+            if required {
+                columns.min_len = idx + 1;
+            }
+        }
+        columns.invalid |= columns.record_type.is_none()
+            || columns.client_id.is_none()
+            || columns.transaction_id.is_none();
+        columns
+    }
+
+    /// Parses the record the same way as the [Transaction] deserialization
+    /// of a trimmed record, without any heap allocation.
+    fn parse(&self, record: &ByteRecord) -> Option<Transaction> {
+        if self.invalid || record.len() < self.min_len {
+            return None;
+        }
+        let columns = [
+            self.record_type,
+            self.client_id,
+            self.transaction_id,
+            self.amount,
+            self.currency,
+        ];
+        let mut fields = [None; 5];
+        for (idx, field) in record.iter().enumerate() {
+            // every field has to be valid UTF-8 like in a StringRecord
+            let field = std::str::from_utf8(field).ok()?.trim();
+            if let Some(column) = columns.iter().position(|column| *column == Some(idx)) {
+                fields[column] = Some(field);
+            }
+        }
+        let [record_type, client_id, transaction_id, amount, currency] = fields;
+        let client_id = u16::try_from(parse_int(client_id?)?).ok()?;
+        let transaction_id = parse_int(transaction_id?)?;
+        let amount = match amount {
+            None | Some("") => None,
+            Some(amount) => Some(Amount::try_new(amount.parse().ok()?)?),
+        };
+        let currency = match currency {
+            None | Some("") => None,
+            Some(currency) => Some(Currency::new(currency)?),
+        };
+        let transaction_type = TransactionType::from_record(record_type?.as_bytes(), amount)?;
+        Some(Transaction {
+            client_id: ClientID(client_id),
+            transaction_id: TransactionID(transaction_id),
+            transaction_type,
+            currency,
+        })
+    }
+}
+
+/// Parses an integer field like the csv deserializer, which accepts a `0x`
+/// prefix for hexadecimal numbers.
+fn parse_int(field: &str) -> Option<u32> {
+    match field.strip_prefix("0x") {
+        Some(digits) => u32::from_str_radix(digits, 16).ok(),
+        None => field.parse().ok(),
+    }
+}
+
+/// [RecordParser] parses the records read by a [TransactionReader] into
+/// transactions. It can be cloned to parse records on other threads, see
+/// [TransactionReader::read_byte_record].
+#[derive(Debug, Clone)]
+pub struct RecordParser {
+    columns: Columns,
+}
+
+impl RecordParser {
+    pub fn parse(&self, record: &ByteRecord) -> Result<Transaction, ReadTransactionError> {
+        self.columns.parse(record).ok_or_else(|| {
+            let line = record.position().map_or(0, |position| position.line());
+            ReadTransactionError::InvalidRecord(line)
         })
     }
 }
+
+/// [TransactionReader] reads transactions from untrusted CSV input without
+/// per row heap allocations. It reuses a single [ByteRecord] and matches the
+/// fields directly instead of deserializing an owned record, accepting the
+/// same input as deserializing [Transaction]s from a [csv_reader].
+pub struct TransactionReader<R> {
+    rdr: csv::Reader<R>,
+    record: ByteRecord,
+    parser: RecordParser,
+}
+
+impl<R: Read> TransactionReader<R> {
+    pub fn new(rdr: R) -> Self {
+        // fields are trimmed when parsed, trimming the records would
+        // allocate
+        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(rdr);
+        let columns = match rdr.byte_headers() {
+            Ok(headers) => Columns::from_headers(headers),
+            Err(_) => Columns::positional(),
+        };
+        Self {
+            rdr,
+            record: ByteRecord::new(),
+            parser: RecordParser { columns },
+        }
+    }
+
+    /// Returns the parser for the records of this input.
+    pub fn parser(&self) -> &RecordParser {
+        &self.parser
+    }
+
+    /// Reads the next record without parsing it. Returns false at the end of
+    /// the input.
+    pub fn read_byte_record(&mut self, record: &mut ByteRecord) -> csv::Result<bool> {
+        self.rdr.read_byte_record(record)
+    }
+
+    /// Returns the line of the last transaction read, 0 before the first.
+    pub fn line(&self) -> u64 {
+        self.record.position().map_or(0, |position| position.line())
+    }
+
+    /// Reads the next transaction. Returns None at the end of the input.
+    pub fn read_transaction(&mut self) -> Option<Result<Transaction, ReadTransactionError>> {
+        match self.rdr.read_byte_record(&mut self.record) {
+            Ok(false) => None,
+            Ok(true) => Some(self.parser.parse(&self.record)),
+            Err(err) => Some(Err(ReadTransactionError::Csv(err))),
+        }
+    }
+}
+
+impl<R: Read> Iterator for TransactionReader<R> {
+    type Item = Result<Transaction, ReadTransactionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_transaction()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::{csv_reader, Transaction, TransactionReader};
+
+    /// Reads the input with both readers and returns the transactions, None
+    /// for rows that could not be read.
+    fn read_both(input: &str) -> (Vec<Option<String>>, Vec<Option<String>>) {
+        let debug = |tx: Option<Transaction>| tx.map(|tx| format!("{tx:?}"));
+        let deserialized = csv_reader(input.as_bytes())
+            .into_deserialize::<Transaction>()
+            .map(|tx| debug(tx.ok()))
+            .collect();
+        let read = TransactionReader::new(input.as_bytes())
+            .map(|tx| debug(tx.ok()))
+            .collect();
+        (deserialized, read)
+    }
+
+    #[test]
+    fn test_transaction_reader() {
+        let input = "type, client ,tx,amount,currency\n\
+                     deposit, 1, 1, 1.5\n\
+                     \u{a0}withdrawal\t,0x2,2,1.0,eur\n\
+                     dispute,1,1\n\
+                     dispute,1,1,,\n\
+                     chargeback,1,1,0.5,\n\
+                     close,1,3,abc\n\
+                     deposit,1,4\n\
+                     Deposit,1,5,1.0\n\
+                     deposit,70000,6,1.0\n\
+                     deposit,-1,7,1.0\n\
+                     deposit,1,8,1.0,EURO\n\
+                     reversal,1,9\n";
+        let (deserialized, read) = read_both(input);
+        assert_eq!(deserialized, read);
+        let valid = read.iter().filter(|tx| tx.is_some()).count();
+        assert_eq!(valid, 6, "{read:?}");
+
+        for headers in [
+            "type,client,tx,amount,note",
+            "client,tx,amount",
+            "type,client,tx,amount,amount",
+            "tx,type,amount,client",
+        ] {
+            let input = format!("{headers}\ndeposit,1,1,1.0\n1,deposit,2.0,3\n");
+            let (deserialized, read) = read_both(&input);
+            assert_eq!(deserialized, read, "{headers}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_amounts() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,inf\n\
+                     deposit,1,2,-inf\n\
+                     deposit,1,3,NaN\n\
+                     deposit,1,4,1e308\n\
+                     withdrawal,1,5,-90071993\n\
+                     deposit,1,6,90071992.5\n";
+        let (deserialized, read) = read_both(input);
+        assert_eq!(deserialized, read);
+        let valid: Vec<_> = read.iter().flatten().collect();
+        assert_eq!(valid.len(), 1, "{read:?}");
+        assert!(valid[0].contains("TransactionID(6)"), "{read:?}");
+    }
+
+    /// Field values exercising the types, headers, integer and amount parsing,
+    /// trimming and quoting.
+    const FIELDS: &[&str] = &[
+        "deposit",
+        "withdrawal",
+        "dispute",
+        "resolve",
+        "chargeback",
+        "reversal",
+        "close",
+        "type",
+        "client",
+        "tx",
+        "amount",
+        "currency",
+        "note",
+        "",
+        " ",
+        "1",
+        "0x1f",
+        "+2",
+        "-1",
+        "65536",
+        "1.5",
+        "-0.25",
+        "1e3",
+        "NaN",
+        "inf",
+        "1e308",
+        "90071993",
+        "EUR",
+        "eu",
+        "\u{a0}",
+        "\"1\"",
+        "\"a,b\"",
+    ];
+
+    fn field() -> impl Strategy<Value = String> {
+        prop_oneof![
+            prop::sample::select(FIELDS).prop_map(String::from),
+            "[ a-z0-9.,\"\t]{0,6}",
+        ]
+    }
+
+    fn line() -> impl Strategy<Value = String> {
+        prop::collection::vec(field(), 0..7).prop_map(|fields| fields.join(","))
+    }
+
+    proptest! {
+        #[test]
+        fn test_transaction_reader_matches_deserialize(
+            headers in prop_oneof![
+                Just("type,client,tx,amount".to_string()),
+                Just("type, client, tx, amount, currency".to_string()),
+                line(),
+            ],
+            lines in prop::collection::vec(line(), 0..20),
+        ) {
+            let input = format!("{headers}\n{}", lines.join("\n"));
+            let (deserialized, read) = read_both(&input);
+            prop_assert_eq!(deserialized, read, "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::{fs::File, io::Write, path::PathBuf};
+
+use anyhow::bail;
+use clap::Parser;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use toy_atm::accounting::common::{Amount, TransactionID};
+
+/// Generates a CSV workload of transactions for the toy ATM.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(flatten)]
+    pub config: WorkloadConfig,
+
+    /// Seed of the random generator. A random seed is used (and printed to
+    /// stderr) if omitted.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Path of the output CSV file, stdout if omitted.
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+/// [WorkloadConfig] describes the shape of the generated workload. The rates
+/// are probabilities per generated row.
+#[derive(clap::Args, Debug, Clone)]
+struct WorkloadConfig {
+    /// Number of rows to generate.
+    #[arg(long, default_value_t = 10_000)]
+    pub rows: u64,
+
+    /// Number of clients, client IDs are 1..=clients.
+    #[arg(long, default_value_t = 100)]
+    pub clients: u16,
+
+    /// Share of withdrawals among the deposits and withdrawals.
+    #[arg(long, default_value_t = 0.3)]
+    pub withdrawal_ratio: f64,
+
+    /// Rate of disputes of earlier deposits and withdrawals.
+    #[arg(long, default_value_t = 0.02)]
+    pub dispute_rate: f64,
+
+    /// Rate of resolves of open disputes.
+    #[arg(long, default_value_t = 0.01)]
+    pub resolve_rate: f64,
+
+    /// Rate of chargebacks of open disputes.
+    #[arg(long, default_value_t = 0.005)]
+    pub chargeback_rate: f64,
+
+    /// Rate of malformed rows (unknown types, missing or invalid amounts and
+    /// IDs).
+    #[arg(long, default_value_t = 0.0)]
+    pub malformed_rate: f64,
+
+    /// Largest deposit or withdrawal amount.
+    #[arg(long, default_value_t = 1000.0)]
+    pub max_amount: f64,
+}
+
+impl WorkloadConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.clients == 0 {
+            bail!("at least one client is required");
+        }
+        let rates = [
+            self.withdrawal_ratio,
+            self.dispute_rate,
+            self.resolve_rate,
+            self.chargeback_rate,
+            self.malformed_rate,
+        ];
+        if rates.iter().any(|rate| !(0.0..=1.0).contains(rate)) {
+            bail!("rates must be between 0 and 1");
+        }
+        if rates[1..].iter().sum::<f64>() > 1.0 {
+            bail!("the dispute, resolve, chargeback and malformed rates exceed 1");
+        }
+        if !(0.0001..=Amount::MAX).contains(&self.max_amount) {
+            bail!("invalid max amount {}", self.max_amount);
+        }
+        Ok(())
+    }
+}
+
+/// [Workload] generates the rows of a workload. Disputes reference earlier
+/// deposits and withdrawals of the same client, resolves and chargebacks
+/// reference open disputes.
+struct Workload {
+    config: WorkloadConfig,
+    rng: StdRng,
+    generated: u64,
+    next_transaction_id: u32,
+
+    /// (client, tx) of the generated deposits and withdrawals.
+    transactions: Vec<(u16, u32)>,
+
+    /// (client, tx) of the generated disputes which are not settled yet.
+    open_disputes: Vec<(u16, u32)>,
+}
+
+type Row = [String; 4];
+
+impl Workload {
+    const HEADER: [&'static str; 4] = ["type", "client", "tx", "amount"];
+
+    fn new(config: WorkloadConfig, seed: u64) -> Self {
+        Self {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            generated: 0,
+            next_transaction_id: 1,
+            transactions: Vec::new(),
+            open_disputes: Vec::new(),
+        }
+    }
+
+    fn row(record_type: &str, client: u16, tx: u32, amount: Option<f64>) -> Row {
+        let amount = amount.map(|amount| format!("{amount:.4}"));
+        [
+            record_type.to_string(),
+            client.to_string(),
+            tx.to_string(),
+            amount.unwrap_or_default(),
+        ]
+    }
+
+    fn amount(&mut self) -> f64 {
+        let amount = self.rng.gen_range(0.0001..=self.config.max_amount);
+        (amount * 10_000.0).round() / 10_000.0
+    }
+
+    /// Returns the next transaction ID, an error once the IDs below the
+    /// ones reserved for the engine are used up.
+    fn transaction_id(&mut self) -> anyhow::Result<u32> {
+        let tx = self.next_transaction_id;
+        match tx.checked_add(1) {
+            Some(next) if !TransactionID(tx).is_internal() => {
+                self.next_transaction_id = next;
+                Ok(tx)
+            }
+            _ => bail!(
+                "ran out of transaction IDs after {} deposits and withdrawals",
+                TransactionID::FIRST_INTERNAL.0 - 1
+            ),
+        }
+    }
+
+    fn deposit_or_withdrawal(&mut self) -> anyhow::Result<Row> {
+        let client = self.rng.gen_range(1..=self.config.clients);
+        let tx = self.transaction_id()?;
+        self.transactions.push((client, tx));
+        let amount = self.amount();
+        Ok(if self.rng.gen_bool(self.config.withdrawal_ratio) {
+            Self::row("withdrawal", client, tx, Some(amount))
+        } else {
+            Self::row("deposit", client, tx, Some(amount))
+        })
+    }
+
+    fn dispute(&mut self) -> Option<Row> {
+        let &(client, tx) = self.transactions.choose(&mut self.rng)?;
+        self.open_disputes.push((client, tx));
+        Some(Self::row("dispute", client, tx, None))
+    }
+
+    fn settle(&mut self, record_type: &str) -> Option<Row> {
+        if self.open_disputes.is_empty() {
+            return None;
+        }
+        let idx = self.rng.gen_range(0..self.open_disputes.len());
+        let (client, tx) = self.open_disputes.swap_remove(idx);
+        Some(Self::row(record_type, client, tx, None))
+    }
+
+    fn malformed(&mut self) -> Row {
+        let client = self.rng.gen_range(1..=self.config.clients);
+        let tx = self.next_transaction_id;
+        match self.rng.gen_range(0..4) {
+            0 => Self::row("transfer", client, tx, Some(1.0)),
+            1 => Self::row("deposit", client, tx, None),
+            2 => [
+                "withdrawal".to_string(),
+                client.to_string(),
+                tx.to_string(),
+                "abc".to_string(),
+            ],
+            _ => [
+                "deposit".to_string(),
+                "-1".to_string(),
+                tx.to_string(),
+                "1.0".to_string(),
+            ],
+        }
+    }
+}
+
+impl Iterator for Workload {
+    type Item = anyhow::Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.generated >= self.config.rows {
+            return None;
+        }
+        self.generated += 1;
+
+        let WorkloadConfig {
+            dispute_rate,
+            resolve_rate,
+            chargeback_rate,
+            malformed_rate,
+            ..
+        } = self.config;
+        let mut r = self.rng.gen::<f64>();
+        let mut next_rate = |rate: f64| {
+            let hit = r < rate;
+            r -= rate;
+            hit
+        };
+        let row = if next_rate(malformed_rate) {
+            Some(self.malformed())
+        } else if next_rate(dispute_rate) {
+            self.dispute()
+        } else if next_rate(resolve_rate) {
+            self.settle("resolve")
+        } else if next_rate(chargeback_rate) {
+            self.settle("chargeback")
+        } else {
+            None
+        };
+        // nothing to dispute or settle yet
+        match row {
+            Some(row) => Some(Ok(row)),
+            None => Some(self.deposit_or_withdrawal()),
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    args.config.validate()?;
+    let seed = args.seed.unwrap_or_else(|| {
+        let seed = rand::random();
+        eprintln!("seed: {seed}");
+        seed
+    });
+
+    let output: Box<dyn Write> = match args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut csv_writer = csv::WriterBuilder::new().flexible(true).from_writer(output);
+    csv_writer.write_record(Workload::HEADER)?;
+    for row in Workload::new(args.config, seed) {
+        csv_writer.write_record(&row?)?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Workload, WorkloadConfig};
+    use toy_atm::accounting::{common::TransactionID, transaction::Transaction};
+
+    fn config() -> WorkloadConfig {
+        WorkloadConfig {
+            rows: 2000,
+            clients: 10,
+            withdrawal_ratio: 0.3,
+            dispute_rate: 0.1,
+            resolve_rate: 0.05,
+            chargeback_rate: 0.02,
+            malformed_rate: 0.01,
+            max_amount: 100.0,
+        }
+    }
+
+    #[test]
+    fn test_workload_is_reproducible() {
+        let rows = |seed| Workload::new(config(), seed).map(Result::unwrap);
+        assert_eq!(rows(7).count(), 2000);
+        assert!(rows(7).eq(rows(7)));
+        assert!(!rows(7).eq(rows(8)));
+    }
+
+    #[test]
+    fn test_workload_rows() {
+        let mut config = config();
+        config.malformed_rate = 0.0;
+        let mut csv = Workload::HEADER.join(",");
+        for row in Workload::new(config.clone(), 1).map(Result::unwrap) {
+            csv.push('\n');
+            csv.push_str(&row.join(","));
+        }
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+        let transactions: Vec<Transaction> = rdr.deserialize().map(Result::unwrap).collect();
+        assert_eq!(transactions.len(), 2000);
+        assert!(transactions
+            .iter()
+            .all(|tx| (1..=config.clients).contains(&tx.client_id.0)));
+
+        config.malformed_rate = 1.0;
+        config.dispute_rate = 0.0;
+        config.resolve_rate = 0.0;
+        config.chargeback_rate = 0.0;
+        let mut csv = Workload::HEADER.join(",");
+        for row in Workload::new(config, 1).map(Result::unwrap) {
+            csv.push('\n');
+            csv.push_str(&row.join(","));
+        }
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+        assert!(rdr.deserialize::<Transaction>().all(|tx| tx.is_err()));
+    }
+
+    #[test]
+    fn test_workload_runs_out_of_transaction_ids() {
+        let mut config = config();
+        config.dispute_rate = 0.0;
+        config.resolve_rate = 0.0;
+        config.chargeback_rate = 0.0;
+        config.malformed_rate = 0.0;
+        let mut workload = Workload::new(config, 1);
+        workload.next_transaction_id = TransactionID::FIRST_INTERNAL.0 - 1;
+        assert!(workload.next().unwrap().is_ok());
+        assert!(workload.next().unwrap().is_err(), "reserved for the engine");
+    }
+
+    #[test]
+    fn test_workload_config_validate() {
+        assert!(config().validate().is_ok());
+        let mut invalid = config();
+        invalid.clients = 0;
+        assert!(invalid.validate().is_err());
+        let mut invalid = config();
+        invalid.dispute_rate = 0.9;
+        invalid.malformed_rate = 0.2;
+        assert!(invalid.validate().is_err());
+        let mut invalid = config();
+        invalid.withdrawal_ratio = -0.1;
+        assert!(invalid.validate().is_err());
+    }
+}
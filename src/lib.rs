@@ -1 +1,5 @@
 pub mod accounting;
+
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod counting_allocator;
@@ -1,38 +1,274 @@
-use clap::Parser;
-use csv::Trim;
+use clap::{Parser, Subcommand};
+use serde::Serialize;
 use std::{fs::File, path::PathBuf};
-use toy_atm::accounting::{atm::Atm, transaction::Transaction};
+use toy_atm::accounting::{
+    atm::{Atm, CurrencyBalanceRow},
+    common::{ClientID, Currency},
+    fees::FeeSchedule,
+    fx::FxRates,
+    interest::InterestSchedule,
+    limits::LimitsConfig,
+    lock_policy::LockPolicy,
+    pipeline::{PipelineConfig, TransactionPipeline},
+    processor::Processor,
+    registry::AccountRegistry,
+    transaction::{ReadTransactionError, Transaction, TransactionReader},
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    pub in_file_path: PathBuf,
+    #[arg(required = true)]
+    pub in_file_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub atm_args: AtmArgs,
+
+    #[command(flatten)]
+    pub reader_args: ReaderArgs,
+
+    /// Writes the accounts ordered by client and currency.
+    #[arg(long)]
+    pub sorted: bool,
+
+    /// Path to a CSV file to write the rejected input rows to, with the
+    /// reason of each.
+    #[arg(long)]
+    pub rejects: Option<PathBuf>,
+
+    /// Path to a CSV file to write the closed accounts to, with the close
+    /// transaction and the paid out amount of each. Closed accounts are left
+    /// out of the balances if set, otherwise they are written as locked
+    /// accounts without funds.
+    #[arg(long)]
+    pub closed_accounts: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+/// [AtmArgs] holds the options used to configure the [Atm].
+#[derive(clap::Args, Debug)]
+struct AtmArgs {
+    /// Path to a TOML file with deposit and withdrawal limits.
+    #[arg(long)]
+    pub limits: Option<PathBuf>,
+
+    /// Path to a TOML file with the fee schedule.
+    #[arg(long)]
+    pub fees: Option<PathBuf>,
+
+    /// Path to a CSV file with exchange rates for withdrawals in another
+    /// currency than the account.
+    #[arg(long)]
+    pub fx_rates: Option<PathBuf>,
+
+    /// Path to a TOML file with the interest schedule.
+    #[arg(long)]
+    pub interest: Option<PathBuf>,
+
+    /// When chargebacks lock an account: first, after:<N>, ratio:<R> (of
+    /// the deposited amount) or never.
+    #[arg(long, default_value_t = LockPolicy::FirstChargeback)]
+    pub lock_policy: LockPolicy,
+
+    /// Resolve and charge back disputes that were already open when the
+    /// account got locked.
+    #[arg(long)]
+    pub settle_locked_disputes: bool,
+
+    /// Path to a CSV file with the registered clients. Enables strict mode
+    /// which ignores the transactions of unregistered clients.
+    #[arg(long)]
+    pub accounts: Option<PathBuf>,
+}
+
+impl AtmArgs {
+    fn build_atm(&self) -> anyhow::Result<Atm> {
+        let mut atm = Atm::default()
+            .with_lock_policy(self.lock_policy)
+            .with_locked_dispute_settlement(self.settle_locked_disputes);
+        if let Some(limits) = &self.limits {
+            atm = atm.with_limits(LimitsConfig::from_file(limits)?);
+        }
+        if let Some(fees) = &self.fees {
+            atm = atm.with_fee_schedule(FeeSchedule::from_file(fees)?);
+        }
+        if let Some(fx_rates) = &self.fx_rates {
+            atm = atm.with_fx_rates(FxRates::from_file(fx_rates)?);
+        }
+        if let Some(interest) = &self.interest {
+            atm = atm.with_interest_schedule(InterestSchedule::from_file(interest)?);
+        }
+        if let Some(accounts) = &self.accounts {
+            atm = atm.with_account_registry(AccountRegistry::from_file(accounts)?);
+        }
+        Ok(atm)
+    }
+}
+
+/// [ReaderArgs] holds the options used to read the input.
+#[derive(clap::Args, Debug)]
+struct ReaderArgs {
+    /// Number of threads parsing the input in a pipeline, 0 parses it on the
+    /// thread handling the transactions.
+    #[arg(long, default_value_t = 0)]
+    pub parser_threads: usize,
+}
+
+impl ReaderArgs {
+    /// Returns the configuration of the [TransactionPipeline], None to read
+    /// on the thread handling the transactions.
+    fn pipeline_config(&self) -> Option<PipelineConfig> {
+        (self.parser_threads > 0)
+            .then(|| PipelineConfig::default().with_parsers(self.parser_threads))
+    }
 
-    let mut atm = Atm::default();
+    /// Reads the transactions of the input, failing on the first row that
+    /// can not be read as a transaction.
+    fn read_transactions(
+        &self,
+        in_file_path: PathBuf,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = anyhow::Result<Transaction>>>> {
+        let input_file = File::open(in_file_path)?;
+        Ok(match self.pipeline_config() {
+            Some(config) => Box::new(
+                TransactionPipeline::new(input_file, config).map(|tx| tx.map_err(read_error)),
+            ),
+            None => Box::new(TransactionReader::new(input_file).map(|tx| tx.map_err(read_error))),
+        })
+    }
+}
 
-    // handle input
-    let input_file = File::open(args.in_file_path)?;
-    let mut rdr = csv::ReaderBuilder::new()
-        .trim(Trim::All)
-        .flexible(true)
-        .from_reader(input_file);
-    for tx in rdr.deserialize::<Transaction>().flatten() {
-        _ = atm.handle_transaction(tx);
+fn read_error(err: ReadTransactionError) -> anyhow::Error {
+    match err {
+        ReadTransactionError::Csv(err) => err.into(),
+        ReadTransactionError::InvalidRecord(line) => {
+            anyhow::anyhow!("invalid transaction on line {line}")
+        }
     }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints the balance of a client right after the N-th handled
+    /// transaction. Fails on rows up to it that can not be read as a
+    /// transaction.
+    BalanceAt {
+        in_file_path: PathBuf,
+
+        #[arg(long)]
+        client: u16,
+
+        /// Currency of the account, the currency-less account if omitted.
+        #[arg(long, value_parser = parse_currency)]
+        currency: Option<Currency>,
+
+        /// Sequence number (1-based) of the handled transaction.
+        #[arg(long)]
+        after: u64,
+
+        #[command(flatten)]
+        atm_args: AtmArgs,
+
+        #[command(flatten)]
+        reader_args: ReaderArgs,
+    },
+
+    /// Prints the fee revenue totals per fee type. Fails on rows that can
+    /// not be read as a transaction.
+    FeeReport {
+        in_file_path: PathBuf,
+
+        #[command(flatten)]
+        atm_args: AtmArgs,
 
-    // print output
+        #[command(flatten)]
+        reader_args: ReaderArgs,
+    },
+}
+
+fn parse_currency(code: &str) -> Result<Currency, String> {
+    Currency::new(code).ok_or_else(|| format!("invalid currency '{code}'"))
+}
+
+fn write_rows(rows: impl Iterator<Item = impl Serialize>) -> anyhow::Result<()> {
     let stdout = std::io::stdout();
     let handle = stdout.lock();
     let mut csv_writer = csv::WriterBuilder::new()
         .has_headers(true)
         .from_writer(handle);
-    for cbs in atm.accounts() {
-        csv_writer.serialize(cbs)?
+    for row in rows {
+        csv_writer.serialize(row)?
     }
-
+    csv_writer.flush()?;
     Ok(())
 }
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    match (args.command, args.in_file_path) {
+        (
+            Some(Command::BalanceAt {
+                in_file_path,
+                client,
+                currency,
+                after,
+                atm_args,
+                reader_args,
+            }),
+            _,
+        ) => {
+            let mut atm = atm_args.build_atm()?;
+            for tx in reader_args.read_transactions(in_file_path)? {
+                if atm.sequence() >= after {
+                    break;
+                }
+                _ = atm.handle_transaction(tx?);
+            }
+            // the input is handled up to the requested transaction
+            let Some(snapshot) = atm.account(ClientID(client), currency) else {
+                anyhow::bail!("client {client} did not exist after transaction {after}");
+            };
+            if currency.is_some() {
+                write_rows(std::iter::once(CurrencyBalanceRow::from(snapshot)))
+            } else {
+                write_rows(std::iter::once(snapshot))
+            }
+        }
+        (
+            Some(Command::FeeReport {
+                in_file_path,
+                atm_args,
+                reader_args,
+            }),
+            _,
+        ) => {
+            let mut atm = atm_args.build_atm()?;
+            for tx in reader_args.read_transactions(in_file_path)? {
+                _ = atm.handle_transaction(tx?);
+            }
+            write_rows(atm.fee_report().rows())
+        }
+        (None, Some(in_file_path)) => {
+            let mut processor =
+                Processor::new(args.atm_args.build_atm()?).with_sorted_output(args.sorted);
+            if let Some(rejects) = &args.rejects {
+                processor = processor.with_rejects(File::create(rejects)?);
+            }
+            if let Some(closed_accounts) = &args.closed_accounts {
+                processor = processor.with_closed_accounts(File::create(closed_accounts)?);
+            }
+            let input_file = File::open(in_file_path)?;
+            let stdout = std::io::stdout().lock();
+            match args.reader_args.pipeline_config() {
+                Some(config) => processor.run_pipelined(input_file, config, stdout)?,
+                None => processor.run(input_file, stdout)?,
+            };
+            Ok(())
+        }
+        (None, None) => unreachable!("clap requires the input file path"),
+    }
+}
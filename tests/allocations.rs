@@ -0,0 +1,46 @@
+//! Checks that [TransactionReader] reads rows without heap allocations once
+//! its buffers are warmed up, counting the allocations of the test thread
+//! with the global allocator of [common].
+
+mod common;
+
+use common::allocations;
+use toy_atm::accounting::transaction::TransactionReader;
+
+#[test]
+fn test_transaction_reader_is_allocation_free() {
+    let mut input = String::from("type, client, tx, amount, currency\n");
+    for tx in 10..10_010u32 {
+        let client = tx % 100;
+        let row = match tx % 8 {
+            0 => format!("dispute, {client}, {}\n", tx - 1),
+            1 => format!("resolve, {client}, {}\n", tx - 2),
+            2 => format!("chargeback,{client},{}\n", tx - 3),
+            3 => format!("withdrawal, {client}, {tx}, 0.5, EUR\n"),
+            4 => format!("dispute, {client}, {}, 0.25\n", tx - 1),
+            5 => format!("  unknown , {client}, {tx}, 1.0\n"),
+            6 => format!("deposit, {client}, {tx}, abc\n"),
+            _ => format!("deposit, {client}, {tx}, {tx}.1234\n"),
+        };
+        input.push_str(&row);
+    }
+
+    let mut reader = TransactionReader::new(input.as_bytes());
+    // the first rows size the buffers of the reader
+    let warm_up: Vec<_> = reader.by_ref().take(8).collect();
+    assert_eq!(warm_up.len(), 8);
+
+    let before = allocations();
+    assert!(before > 0, "counts the allocations of the test thread");
+    let (mut transactions, mut invalid) = (0, 0);
+    for tx in reader.by_ref() {
+        match tx {
+            Ok(_) => transactions += 1,
+            Err(_) => invalid += 1,
+        }
+    }
+    let allocated = allocations() - before;
+    assert_eq!(transactions + invalid, 10_000 - 8);
+    assert!(invalid > 0 && transactions > invalid);
+    assert_eq!(allocated, 0, "allocations while reading");
+}
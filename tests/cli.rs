@@ -0,0 +1,172 @@
+//! Golden-file tests running the built binary on the inputs in `tests/data`.
+//! Every case directory holds an `input.csv` and the `expected.csv` output,
+//! and optionally the `closed.csv` closed accounts.
+
+use std::{path::Path, process::Command};
+
+/// Splits the CSV into its header and its rows sorted.
+fn sorted_rows(csv: &str) -> (String, Vec<String>) {
+    let mut lines = csv.lines().map(str::to_string);
+    let header = lines.next().unwrap_or_default();
+    let mut rows: Vec<_> = lines.collect();
+    rows.sort();
+    (header, rows)
+}
+
+/// Runs the case parsing the input on the handling thread and with one and
+/// several parser threads.
+fn run_case(case: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(case);
+    let expected = std::fs::read_to_string(dir.join("expected.csv")).unwrap();
+    let expected_closed = std::fs::read_to_string(dir.join("closed.csv")).ok();
+    for parser_threads in ["0", "1", "3"] {
+        let closed = Path::new(env!("CARGO_TARGET_TMPDIR"))
+            .join(format!("{case}_{parser_threads}_closed.csv"));
+        let mut command = Command::new(env!("CARGO_BIN_EXE_toy_atm"));
+        command
+            .arg(dir.join("input.csv"))
+            .args(["--parser-threads", parser_threads]);
+        if expected_closed.is_some() {
+            command.arg("--closed-accounts").arg(&closed);
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{case}: {output:?}");
+        assert_eq!(
+            sorted_rows(&String::from_utf8(output.stdout).unwrap()),
+            sorted_rows(&expected),
+            "{case} with {parser_threads} parser threads"
+        );
+        if let Some(expected_closed) = &expected_closed {
+            assert_eq!(
+                sorted_rows(&std::fs::read_to_string(&closed).unwrap()),
+                sorted_rows(expected_closed),
+                "{case} closed accounts with {parser_threads} parser threads"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_whitespace() {
+    run_case("whitespace");
+}
+
+#[test]
+fn test_missing_amount() {
+    run_case("missing_amount");
+}
+
+#[test]
+fn test_unknown_types() {
+    run_case("unknown_types");
+}
+
+#[test]
+fn test_dispute_missing_id() {
+    run_case("dispute_missing_id");
+}
+
+#[test]
+fn test_locked_account() {
+    run_case("locked_account");
+}
+
+#[test]
+fn test_closed_account() {
+    run_case("closed_account");
+}
+
+#[test]
+fn test_missing_input_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_toy_atm"))
+        .arg("does-not-exist.csv")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_sorted_output_and_rejects() {
+    let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/locked_account/input.csv");
+    let rejects = Path::new(env!("CARGO_TARGET_TMPDIR")).join("locked_account_rejects.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_toy_atm"))
+        .arg(input)
+        .arg("--sorted")
+        .arg("--rejects")
+        .arg(&rejects)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,10.0,0.0,10.0,true\n\
+         2,1.0,0.0,1.0,false\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(rejects).unwrap(),
+        "line,client,tx,reason\n\
+         6,1,3,LockedAccount\n\
+         7,1,4,LockedAccount\n\
+         8,1,1,LockedAccount\n"
+    );
+}
+
+#[test]
+fn test_balance_at() {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    for parser_threads in ["0", "3"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_toy_atm"))
+            .arg("balance-at")
+            .arg(data.join("locked_account/input.csv"))
+            .args(["--client", "1", "--after", "3"])
+            .args(["--parser-threads", parser_threads])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "client,available,held,total,locked\n\
+             1,10.0,5.0,15.0,false\n"
+        );
+
+        // unreadable rows fail the command instead of being skipped
+        for command in [
+            &["balance-at", "--client", "1", "--after", "5"][..],
+            &["fee-report"][..],
+        ] {
+            let output = Command::new(env!("CARGO_BIN_EXE_toy_atm"))
+                .arg(command[0])
+                .arg(data.join("unknown_types/input.csv"))
+                .args(&command[1..])
+                .args(["--parser-threads", parser_threads])
+                .output()
+                .unwrap();
+            assert!(!output.status.success(), "{command:?}: {output:?}");
+            assert!(output.stdout.is_empty());
+            assert!(String::from_utf8(output.stderr)
+                .unwrap()
+                .contains("invalid transaction on line 3"));
+        }
+    }
+}
+
+#[test]
+fn test_closed_account_without_sink() {
+    let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/closed_account/input.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_toy_atm"))
+        .arg(input)
+        .arg("--sorted")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,0.0,0.0,0.0,true\n\
+         2,0.0,5.0,5.0,false\n"
+    );
+}
@@ -0,0 +1,55 @@
+//! Global allocator counting the allocations and the live heap bytes of each
+//! thread, shared by the integration tests and the unit tests of the crate
+//! which include this file with `#[path]`. Each of them uses a part of it.
+#![allow(dead_code)]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    static LIVE_BYTES: Cell<isize> = const { Cell::new(0) };
+}
+
+fn count(allocations: u64, bytes: isize) {
+    // the counts are gone while the thread is torn down
+    _ = ALLOCATIONS.try_with(|count| count.set(count.get() + allocations));
+    _ = LIVE_BYTES.try_with(|live| live.set(live.get() + bytes));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(1, layout.size() as isize);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count(0, -(layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(1, new_size as isize - layout.size() as isize);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the number of allocations of the current thread so far.
+pub fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// Returns the value built by `f` and the bytes it holds on the heap.
+pub fn heap_bytes_of<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = LIVE_BYTES.with(Cell::get);
+    let value = f();
+    let after = LIVE_BYTES.with(Cell::get);
+    (value, (after - before) as usize)
+}